@@ -0,0 +1,319 @@
+use serde::{Serialize, Deserialize};
+
+use std::collections::{HashMap, HashSet};
+
+use crate::utils;
+
+
+/// How many people watched a stream and for how long.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ViewerStats {
+    /// The most viewers in the room at once.
+    pub peak_viewers: usize,
+
+    /// When the peak was first reached as a unix timestamp in ms.
+    pub peak_at: Option<u64>,
+
+    /// The amount of different users that watched.
+    pub unique_viewers: usize,
+
+    /// The amount of times a user joined the room, not counting users
+    /// that were already there when the stream started.
+    pub joins: usize,
+
+    /// The amount of times a user left the room.
+    pub leaves: usize,
+
+    /// The time every viewer spent in the room in ms.
+    pub total_watch_time: u64,
+
+    /// The time each unique viewer spent in the room on average in ms.
+    pub average_watch_time: u64,
+}
+
+
+/// The summary of a stream once it has ended.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StreamSummary {
+    /// When the stream was published as a unix timestamp in ms.
+    pub started_at: Option<u64>,
+
+    /// When the stream ended as a unix timestamp in ms.
+    pub ended_at: u64,
+
+    /// Why the stream ended.
+    pub end_reason: Option<String>,
+
+    /// Who watched the stream.
+    pub viewers: ViewerStats,
+}
+
+
+/// Counts the viewers of a room's current or most recent stream.
+///
+/// Viewers are counted from when the stream is published until it ends,
+/// anyone already in the room when it is published counts as watching
+/// from the start.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ViewerAnalytics {
+    /// If a stream is published and viewers are being counted.
+    active: bool,
+
+    /// When the stream was published as a unix timestamp in ms.
+    started_at: Option<u64>,
+
+    peak_viewers: usize,
+    peak_at: Option<u64>,
+
+    /// Every user that has watched.
+    viewers: HashSet<String>,
+
+    joins: usize,
+    leaves: usize,
+
+    /// The time viewers that have since left spent in the room in ms.
+    watch_time: u64,
+
+    /// The summary of the most recent stream to end.
+    last_stream: Option<StreamSummary>,
+
+    /// When each user currently watching joined as a unix timestamp in ms.
+    #[serde(skip)]
+    watching: HashMap<String, u64>,
+}
+
+impl ViewerAnalytics {
+    /// Starts counting a new stream's viewers, the users given are
+    /// already in the room.
+    pub fn start(&mut self, present: Vec<String>) {
+        let now = utils::now_millis();
+
+        *self = Self {
+            active: true,
+            started_at: Some(now),
+            last_stream: self.last_stream.take(),
+            ..Self::default()
+        };
+
+        for user_id in present {
+            self.viewers.insert(user_id.clone());
+            self.watching.insert(user_id, now);
+        }
+        self.update_peak(now);
+    }
+
+    /// Records a user joining the room.
+    pub fn join(&mut self, user_id: &str) {
+        if !self.active {
+            return
+        }
+
+        let now = utils::now_millis();
+        self.joins += 1;
+        self.viewers.insert(user_id.to_string());
+        self.watching.insert(user_id.to_string(), now);
+        self.update_peak(now);
+    }
+
+    /// Records a user leaving the room.
+    pub fn leave(&mut self, user_id: &str) {
+        if !self.active {
+            return
+        }
+
+        if let Some(joined_at) = self.watching.remove(user_id) {
+            self.leaves += 1;
+            self.watch_time += utils::now_millis().saturating_sub(joined_at);
+        }
+    }
+
+    /// Stops counting viewers as the stream has ended, returning its
+    /// summary or None if no stream was being counted.
+    pub fn finish(&mut self, end_reason: Option<String>) -> Option<StreamSummary> {
+        if !self.active {
+            return None
+        }
+
+        let now = utils::now_millis();
+        for (_, joined_at) in self.watching.drain() {
+            self.watch_time += now.saturating_sub(joined_at);
+        }
+        self.active = false;
+
+        let summary = StreamSummary {
+            started_at: self.started_at,
+            ended_at: now,
+            end_reason,
+            viewers: self.stats(),
+        };
+        self.last_stream = Some(summary.clone());
+
+        Some(summary)
+    }
+
+    /// The viewer numbers so far, including the time of everyone still
+    /// watching.
+    pub fn stats(&self) -> ViewerStats {
+        let now = utils::now_millis();
+        let watching: u64 = self.watching
+            .values()
+            .map(|joined_at| now.saturating_sub(*joined_at))
+            .sum();
+
+        let total_watch_time = self.watch_time + watching;
+        let average_watch_time = total_watch_time
+            .checked_div(self.viewers.len() as u64)
+            .unwrap_or(0);
+
+        ViewerStats {
+            peak_viewers: self.peak_viewers,
+            peak_at: self.peak_at,
+            unique_viewers: self.viewers.len(),
+            joins: self.joins,
+            leaves: self.leaves,
+            total_watch_time,
+            average_watch_time,
+        }
+    }
+
+    /// The summary of the most recent stream to end.
+    pub fn last_stream(&self) -> Option<StreamSummary> {
+        self.last_stream.clone()
+    }
+
+    fn update_peak(&mut self, now: u64) {
+        if self.watching.len() > self.peak_viewers {
+            self.peak_viewers = self.watching.len();
+            self.peak_at = Some(now);
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Pretends everyone watching joined `ms` earlier.
+    fn rewind(analytics: &mut ViewerAnalytics, ms: u64) {
+        for joined_at in analytics.watching.values_mut() {
+            *joined_at -= ms;
+        }
+    }
+
+    fn users(ids: &[&str]) -> Vec<String> {
+        ids.iter().map(|id| id.to_string()).collect()
+    }
+
+    /// Checks a time in ms allowing for the time the test takes to run.
+    fn assert_about(ms: u64, expected: u64) {
+        assert!((expected..expected + 1_000).contains(&ms), "{} is not about {}", ms, expected);
+    }
+
+    #[test]
+    fn peak_is_the_most_viewers_at_once() {
+        let mut analytics = ViewerAnalytics::default();
+        analytics.start(users(&["a"]));
+        assert_eq!(analytics.stats().peak_viewers, 1);
+
+        analytics.join("b");
+        let stats = analytics.stats();
+        assert_eq!(stats.peak_viewers, 2);
+        assert!(stats.peak_at.is_some());
+
+        // Reaching the same peak again keeps when it was first reached.
+        analytics.peak_at = Some(1);
+        analytics.leave("a");
+        analytics.join("c");
+        assert_eq!(analytics.stats().peak_viewers, 2);
+        assert_eq!(analytics.stats().peak_at, Some(1));
+
+        analytics.join("d");
+        assert_eq!(analytics.stats().peak_viewers, 3);
+        assert_ne!(analytics.stats().peak_at, Some(1));
+    }
+
+    #[test]
+    fn repeat_joins_are_one_unique_viewer() {
+        let mut analytics = ViewerAnalytics::default();
+        analytics.start(users(&["a"]));
+        analytics.leave("a");
+        analytics.join("a");
+        analytics.join("b");
+        analytics.leave("b");
+        analytics.join("b");
+
+        let stats = analytics.stats();
+        assert_eq!(stats.unique_viewers, 2);
+        assert_eq!(stats.joins, 3);
+        assert_eq!(stats.leaves, 2);
+    }
+
+    #[test]
+    fn leaving_twice_is_one_leave() {
+        let mut analytics = ViewerAnalytics::default();
+        analytics.start(users(&["a"]));
+        analytics.leave("a");
+        analytics.leave("a");
+        analytics.leave("b");
+        assert_eq!(analytics.stats().leaves, 1);
+    }
+
+    #[test]
+    fn watch_time_counts_everyone_in_the_room() {
+        let mut analytics = ViewerAnalytics::default();
+        analytics.start(users(&["a"]));
+        rewind(&mut analytics, 10_000);
+
+        analytics.join("b");
+        rewind(&mut analytics, 5_000);
+        analytics.leave("b");
+
+        let stats = analytics.stats();
+        assert_about(stats.total_watch_time, 20_000);
+        assert_about(stats.average_watch_time, 10_000);
+
+        analytics.join("b");
+        rewind(&mut analytics, 5_000);
+
+        // a and b are still watching when the stream ends.
+        let summary = analytics.finish(Some("unpublished".to_string())).unwrap();
+        assert_eq!(summary.end_reason.as_deref(), Some("unpublished"));
+        assert_about(summary.viewers.total_watch_time, 30_000);
+        assert_about(summary.viewers.average_watch_time, 15_000);
+        assert_eq!(summary.viewers.unique_viewers, 2);
+
+        // Nothing is counted once the stream has ended.
+        assert_about(analytics.stats().total_watch_time, 30_000);
+    }
+
+    #[test]
+    fn nothing_is_counted_without_a_stream() {
+        let mut analytics = ViewerAnalytics::default();
+        analytics.join("a");
+        analytics.leave("a");
+        assert!(analytics.finish(None).is_none());
+
+        let stats = analytics.stats();
+        assert_eq!(stats.unique_viewers, 0);
+        assert_eq!(stats.joins, 0);
+        assert_eq!(stats.average_watch_time, 0);
+    }
+
+    #[test]
+    fn last_stream_is_kept_until_the_next_one_ends() {
+        let mut analytics = ViewerAnalytics::default();
+        analytics.start(users(&["a"]));
+        analytics.finish(Some("first".to_string()));
+        assert!(analytics.finish(None).is_none());
+
+        analytics.start(users(&[]));
+        assert_eq!(analytics.stats().unique_viewers, 0);
+        let last = analytics.last_stream().unwrap();
+        assert_eq!(last.end_reason.as_deref(), Some("first"));
+        assert_eq!(last.viewers.unique_viewers, 1);
+
+        analytics.finish(Some("second".to_string()));
+        assert_eq!(analytics.last_stream().unwrap().end_reason.as_deref(), Some("second"));
+    }
+}
@@ -0,0 +1,482 @@
+use warp::{Filter, Rejection, Reply};
+use warp::body::BodyDeserializeError;
+use warp::filters::path::FullPath;
+use warp::http::{Method, StatusCode};
+use warp::reject::{
+    InvalidHeader,
+    InvalidQuery,
+    LengthRequired,
+    MethodNotAllowed,
+    MissingHeader,
+    PayloadTooLarge,
+    Reject,
+    UnsupportedMediaType,
+};
+use warp::ws::MissingConnectionUpgrade;
+use warp::reply;
+
+use bytes::Bytes;
+use hmac::{Hmac, Mac};
+use sha2::{Digest, Sha256};
+
+use serde::de::DeserializeOwned;
+use serde_json::json;
+
+use crate::config;
+
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// The max amount of seconds a signed request's timestamp is allowed to
+/// drift from the gateway's clock before it is considered a replay.
+const MAX_SIGNATURE_AGE: u64 = 300;
+
+/// The max size in bytes of a request body.
+const MAX_BODY_SIZE: u64 = 64 * 1024;
+
+/// The header containing the unix timestamp a request was signed at.
+const TIMESTAMP_HEADER: &str = "x-gateway-timestamp";
+
+/// The header containing the hex encoded HMAC-SHA256 signature.
+const SIGNATURE_HEADER: &str = "x-gateway-signature";
+
+
+/// The reasons a request can be refused by the admin api.
+#[derive(Debug)]
+pub enum AuthError {
+    /// No credentials were given at all.
+    MissingCredentials,
+
+    /// The credentials were given but were malformed.
+    MalformedCredentials,
+
+    /// The signed request is too far away from the current time.
+    ExpiredSignature,
+
+    /// The credentials did not match any configured key.
+    InvalidCredentials,
+}
+
+impl AuthError {
+    fn status(&self) -> StatusCode {
+        match self {
+            Self::MissingCredentials => StatusCode::UNAUTHORIZED,
+            Self::MalformedCredentials => StatusCode::UNAUTHORIZED,
+            Self::ExpiredSignature => StatusCode::FORBIDDEN,
+            Self::InvalidCredentials => StatusCode::FORBIDDEN,
+        }
+    }
+
+    fn message(&self) -> &'static str {
+        match self {
+            Self::MissingCredentials => "Missing api key or request signature.",
+            Self::MalformedCredentials => "Malformed authorization header or signature.",
+            Self::ExpiredSignature => "The request signature has expired.",
+            Self::InvalidCredentials => "Invalid api key or request signature.",
+        }
+    }
+}
+
+impl Reject for AuthError {}
+
+
+/// A request body that could not be decoded once it was authenticated.
+#[derive(Debug)]
+pub struct InvalidBody(String);
+
+impl Reject for InvalidBody {}
+
+
+/// Logs how many admin keys are loaded, warning if the admin api
+/// is going to refuse every request.
+pub fn log_key_status() {
+    let config = config::get();
+
+    if config.admin_api_keys.is_empty() {
+        tracing::warn!("No admin api keys set, all admin routes will refuse requests");
+    } else {
+        tracing::info!(count = config.admin_api_keys.len(), "Loaded admin api keys");
+    }
+
+    if !config.webhook_keys.is_empty() {
+        tracing::info!(count = config.webhook_keys.len(), "Loaded webhook keys");
+    }
+}
+
+
+/// A filter that only passes requests carrying a valid admin api key,
+/// for routes that do not take a body.
+///
+/// A request can either send one of the keys directly as a bearer token:
+///
+/// `Authorization: Bearer <key>`
+///
+/// Or sign the request with one of the keys, sending the unix timestamp
+/// in `X-Gateway-Timestamp` and the hex HMAC-SHA256 of
+///
+/// `"{timestamp}\n{METHOD}\n{path}\n{query}\n{body_sha256}"`
+///
+/// in `X-Gateway-Signature`. `query` is the raw query string without the
+/// `?` or empty if there is none and `body_sha256` is the hex SHA-256 of
+/// the raw body, these routes are signed as having an empty body.
+pub fn require_api_key() -> impl Filter<Extract = (), Error = Rejection> + Clone {
+    credentials()
+        .and_then(|credentials: Credentials| async move {
+            credentials.check(&[]).map_err(warp::reject::custom)
+        })
+        .untuple_one()
+}
+
+
+/// A filter giving if the request carries a valid admin api key the same
+/// way as `require_api_key`, for routes open to anyone that show admins
+/// more.
+pub fn has_api_key() -> impl Filter<Extract = (bool,), Error = Rejection> + Clone {
+    credentials().map(|credentials: Credentials| credentials.check(&[]).is_ok())
+}
+
+
+/// A filter that only passes requests carrying a valid admin api key
+/// the same way as `require_api_key`, giving the raw body it was signed
+/// with.
+pub fn signed_body() -> impl Filter<Extract = (Bytes,), Error = Rejection> + Clone {
+    credentials()
+        .and(warp::body::content_length_limit(MAX_BODY_SIZE))
+        .and(warp::body::bytes())
+        .and_then(|credentials: Credentials, body: Bytes| async move {
+            credentials
+                .check(&body)
+                .map(|_| body)
+                .map_err(warp::reject::custom)
+        })
+}
+
+
+/// A filter that only passes requests carrying a valid admin api key
+/// the same way as `require_api_key`, decoding the JSON body.
+pub fn signed_json<T>() -> impl Filter<Extract = (T,), Error = Rejection> + Clone
+where
+    T: DeserializeOwned + Send,
+{
+    signed_body()
+        .and_then(|body: Bytes| async move {
+            serde_json::from_slice(&body)
+                .map_err(|e| warp::reject::custom(InvalidBody(e.to_string())))
+        })
+}
+
+
+/// A filter that only passes requests from the live server's webhooks,
+/// decoding the form encoded body.
+///
+/// The live server passes one of the webhook keys in the url as
+/// `?key=<key>` as most live servers can not set headers on their
+/// callbacks, anything that can send an admin api key or signature is
+/// also let through.
+///
+/// The webhook keys are kept apart from the admin keys so the key
+/// written into the live server's config can only ever report stream
+/// events.
+pub fn webhook_form<T>() -> impl Filter<Extract = (T,), Error = Rejection> + Clone
+where
+    T: DeserializeOwned + Send,
+{
+    warp::query::<HashMap<String, String>>()
+        .and(credentials())
+        .and(warp::body::content_length_limit(MAX_BODY_SIZE))
+        .and(warp::body::bytes())
+        .and_then(|query: HashMap<String, String>, credentials: Credentials, body: Bytes| async move {
+            match query.get("key") {
+                Some(key) => check_key(&config::get().webhook_keys, key),
+                None => credentials.check(&body),
+            }.map_err(warp::reject::custom)?;
+
+            serde_urlencoded::from_bytes(&body)
+                .map_err(|e| warp::reject::custom(InvalidBody(e.to_string())))
+        })
+}
+
+
+/// The admin credentials a request may have been sent with.
+struct Credentials {
+    authorization: Option<String>,
+    timestamp: Option<String>,
+    signature: Option<String>,
+    method: Method,
+    path: FullPath,
+    query: String,
+}
+
+impl Credentials {
+    /// Checks the credentials against the admin keys, a signature must
+    /// also cover the given body.
+    fn check(&self, body: &[u8]) -> Result<(), AuthError> {
+        match (&self.authorization, &self.timestamp, &self.signature) {
+            (Some(authorization), _, _) => check_bearer(authorization),
+            (None, Some(timestamp), Some(signature)) => {
+                let message = signed_message(
+                    timestamp,
+                    &self.method,
+                    self.path.as_str(),
+                    &self.query,
+                    body,
+                );
+                check_signature(&config::get().admin_api_keys, timestamp, signature, &message)
+            },
+            _ => Err(AuthError::MissingCredentials),
+        }
+    }
+}
+
+
+/// Extracts the admin credentials from a request.
+fn credentials() -> impl Filter<Extract = (Credentials,), Error = Rejection> + Clone {
+    warp::header::optional::<String>("authorization")
+        .and(warp::header::optional::<String>(TIMESTAMP_HEADER))
+        .and(warp::header::optional::<String>(SIGNATURE_HEADER))
+        .and(warp::method())
+        .and(warp::path::full())
+        .and(raw_query())
+        .map(|
+            authorization: Option<String>,
+            timestamp: Option<String>,
+            signature: Option<String>,
+            method: Method,
+            path: FullPath,
+            query: String,
+        | Credentials {
+            authorization,
+            timestamp,
+            signature,
+            method,
+            path,
+            query,
+        })
+}
+
+
+/// Extracts the raw query string, empty if the request has none.
+fn raw_query() -> impl Filter<Extract = (String,), Error = std::convert::Infallible> + Clone {
+    warp::query::raw()
+        .or(warp::any().map(String::new))
+        .unify()
+}
+
+
+/// Builds the canonical form of a request that is signed.
+fn signed_message(
+    timestamp: &str,
+    method: &Method,
+    path: &str,
+    query: &str,
+    body: &[u8],
+) -> String {
+    format!(
+        "{}\n{}\n{}\n{}\n{}",
+        timestamp,
+        method.as_str(),
+        path,
+        query,
+        hex::encode(Sha256::digest(body)),
+    )
+}
+
+
+/// Checks a `Bearer <key>` authorization header against the admin keys.
+fn check_bearer(authorization: &str) -> Result<(), AuthError> {
+    let key = authorization
+        .strip_prefix("Bearer ")
+        .ok_or(AuthError::MalformedCredentials)?;
+
+    check_key(&config::get().admin_api_keys, key)
+}
+
+
+/// Checks a key against a list of known keys.
+fn check_key(keys: &[String], key: &str) -> Result<(), AuthError> {
+    let is_valid = keys
+        .iter()
+        .any(|known| constant_time_eq(known.as_bytes(), key.trim().as_bytes()));
+
+    if is_valid {
+        Ok(())
+    } else {
+        Err(AuthError::InvalidCredentials)
+    }
+}
+
+
+/// Checks the signature of a request's canonical form against each of
+/// the given keys.
+fn check_signature(
+    keys: &[String],
+    timestamp: &str,
+    signature: &str,
+    message: &str,
+) -> Result<(), AuthError> {
+    let signed_at = timestamp
+        .parse::<u64>()
+        .map_err(|_| AuthError::MalformedCredentials)?;
+    let signature = hex::decode(signature)
+        .map_err(|_| AuthError::MalformedCredentials)?;
+
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+
+    if now.max(signed_at) - now.min(signed_at) > MAX_SIGNATURE_AGE {
+        return Err(AuthError::ExpiredSignature)
+    }
+
+    for key in keys.iter() {
+        let mut mac = HmacSha256::new_from_slice(key.as_bytes())
+            .expect("HMAC can take a key of any size");
+        mac.update(message.as_bytes());
+
+        if mac.verify_slice(&signature).is_ok() {
+            return Ok(())
+        }
+    }
+
+    Err(AuthError::InvalidCredentials)
+}
+
+
+/// Compares two byte strings without exiting early on the first
+/// difference.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false
+    }
+
+    a.iter().zip(b.iter()).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+
+/// Turns every rejection into the standard JSON error body, anything
+/// the gateway does not know about is logged and answered with a 500.
+pub async fn handle_rejection(err: Rejection) -> Result<impl Reply, Infallible> {
+    let (status, message) = if err.is_not_found() {
+        (StatusCode::NOT_FOUND, "Not found.".to_string())
+    } else if let Some(auth_err) = err.find::<AuthError>() {
+        (auth_err.status(), auth_err.message().to_string())
+    } else if let Some(body_err) = err.find::<BodyDeserializeError>() {
+        (StatusCode::BAD_REQUEST, body_err.to_string())
+    } else if let Some(InvalidBody(message)) = err.find::<InvalidBody>() {
+        (StatusCode::BAD_REQUEST, format!("Request body deserialize error: {}", message))
+    } else if let Some(e) = err.find::<InvalidQuery>() {
+        (StatusCode::BAD_REQUEST, e.to_string())
+    } else if let Some(e) = err.find::<MissingHeader>() {
+        (StatusCode::BAD_REQUEST, e.to_string())
+    } else if let Some(e) = err.find::<InvalidHeader>() {
+        (StatusCode::BAD_REQUEST, e.to_string())
+    } else if let Some(e) = err.find::<MissingConnectionUpgrade>() {
+        (StatusCode::BAD_REQUEST, e.to_string())
+    } else if let Some(e) = err.find::<LengthRequired>() {
+        (StatusCode::LENGTH_REQUIRED, e.to_string())
+    } else if let Some(e) = err.find::<PayloadTooLarge>() {
+        (StatusCode::PAYLOAD_TOO_LARGE, e.to_string())
+    } else if let Some(e) = err.find::<UnsupportedMediaType>() {
+        (StatusCode::UNSUPPORTED_MEDIA_TYPE, e.to_string())
+    } else if let Some(e) = err.find::<MethodNotAllowed>() {
+        (StatusCode::METHOD_NOT_ALLOWED, e.to_string())
+    } else {
+        tracing::error!(rejection = ?err, "Unhandled rejection");
+        (StatusCode::INTERNAL_SERVER_ERROR, "Internal server error.".to_string())
+    };
+
+    let body = json!({
+        "status": status.as_u16(),
+        "message": message,
+    });
+
+    Ok(reply::with_status(reply::json(&body), status))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn keys() -> Vec<String> {
+        vec!["first".to_string(), "second".to_string()]
+    }
+
+    fn now() -> u64 {
+        SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs()
+    }
+
+    fn sign(key: &str, message: &str) -> String {
+        let mut mac = HmacSha256::new_from_slice(key.as_bytes()).unwrap();
+        mac.update(message.as_bytes());
+        hex::encode(mac.finalize().into_bytes())
+    }
+
+    fn check(key: &str, signed_at: u64) -> Result<(), AuthError> {
+        let timestamp = signed_at.to_string();
+        let message = signed_message(&timestamp, &Method::POST, "/v1/rooms", "a=1", b"{}");
+        check_signature(&keys(), &timestamp, &sign(key, &message), &message)
+    }
+
+    #[test]
+    fn signed_message_covers_the_body_hash() {
+        let message = signed_message("10", &Method::GET, "/rooms", "", b"");
+        assert_eq!(
+            message,
+            "10\nGET\n/rooms\n\ne3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855",
+        );
+    }
+
+    #[test]
+    fn signature_with_any_key_passes() {
+        assert!(check("first", now()).is_ok());
+        assert!(check("second", now()).is_ok());
+    }
+
+    #[test]
+    fn signature_with_unknown_key_fails() {
+        assert!(matches!(check("third", now()), Err(AuthError::InvalidCredentials)));
+    }
+
+    #[test]
+    fn signature_over_another_message_fails() {
+        let timestamp = now().to_string();
+        let message = signed_message(&timestamp, &Method::POST, "/v1/rooms", "a=1", b"{}");
+        let tampered = signed_message(&timestamp, &Method::POST, "/v1/rooms", "a=2", b"{}");
+
+        let result = check_signature(&keys(), &timestamp, &sign("first", &message), &tampered);
+        assert!(matches!(result, Err(AuthError::InvalidCredentials)));
+    }
+
+    #[test]
+    fn signature_outside_max_age_expires() {
+        let too_old = now() - MAX_SIGNATURE_AGE - 10;
+        let too_new = now() + MAX_SIGNATURE_AGE + 10;
+
+        assert!(check("first", now() - MAX_SIGNATURE_AGE + 10).is_ok());
+        assert!(matches!(check("first", too_old), Err(AuthError::ExpiredSignature)));
+        assert!(matches!(check("first", too_new), Err(AuthError::ExpiredSignature)));
+    }
+
+    #[test]
+    fn malformed_signature_is_refused() {
+        let result = check_signature(&keys(), "soon", "00", "message");
+        assert!(matches!(result, Err(AuthError::MalformedCredentials)));
+
+        let result = check_signature(&keys(), &now().to_string(), "not hex", "message");
+        assert!(matches!(result, Err(AuthError::MalformedCredentials)));
+    }
+
+    #[test]
+    fn constant_time_eq_compares_bytes() {
+        assert!(constant_time_eq(b"", b""));
+        assert!(constant_time_eq(b"secret", b"secret"));
+        assert!(!constant_time_eq(b"secret", b"secreT"));
+        assert!(!constant_time_eq(b"secret", b"secrets"));
+        assert!(!constant_time_eq(b"", b"s"));
+    }
+}
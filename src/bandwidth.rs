@@ -0,0 +1,330 @@
+use serde::{Serialize, Deserialize};
+use tokio::time::Duration;
+
+use std::collections::{HashMap, VecDeque};
+
+use crate::config;
+use crate::utils;
+
+/// The amount of recent violations each room keeps in its stats.
+const VIOLATION_HISTORY: usize = 20;
+
+/// The amount of recent samples a stream's rate is averaged over when
+/// checking it against the ceilings.
+const RATE_WINDOW: usize = 5;
+
+/// How long the live server is given to answer a limit request.
+const LIMIT_REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+
+lazy_static! {
+    static ref CLIENT: reqwest::Client = reqwest::Client::new();
+}
+
+
+/// Which ceiling a stream went over.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LimitScope {
+    /// The room's own `byte_rate_limit`.
+    Room,
+
+    /// `GLOBAL_BYTE_RATE_LIMIT` across every live room.
+    Global,
+}
+
+impl LimitScope {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Room => "room",
+            Self::Global => "global",
+        }
+    }
+}
+
+
+/// What the live server is asked to do with a publisher over the limit.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LimitAction {
+    /// Lower the rate the publisher may send at.
+    Throttle,
+
+    /// Disconnect the publisher.
+    Kick,
+}
+
+
+/// A time a room's stream went over a bandwidth ceiling.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Violation {
+    /// When the stream was found over the ceiling as a unix timestamp in ms.
+    pub at: u64,
+
+    /// Which ceiling it went over.
+    pub scope: LimitScope,
+
+    /// The stream's rate over its last few samples in bytes per second,
+    /// or every live stream's combined rate for the global ceiling.
+    pub byte_rate: usize,
+
+    /// The ceiling in bytes per second.
+    pub limit: usize,
+
+    /// What the live server was asked to do, None if
+    /// `BANDWIDTH_LIMIT_ENDPOINT` is not set.
+    pub action: Option<LimitAction>,
+
+    /// Why the live server could not be asked.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+
+/// The bandwidth ceilings a room's stream has gone over.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct BandwidthState {
+    /// The amount of times the stream has been found over a ceiling.
+    violations: usize,
+
+    /// The most recent violations, oldest first.
+    recent: VecDeque<Violation>,
+
+    /// The stream's last `RATE_WINDOW` sampled rates, oldest first.
+    #[serde(skip)]
+    rates: VecDeque<usize>,
+
+    /// When the stream was last warned about each ceiling it is still
+    /// over as a unix timestamp in ms.
+    #[serde(skip)]
+    warned_at: HashMap<LimitScope, u64>,
+}
+
+impl BandwidthState {
+    /// Adds a sampled rate, returning the stream's current rate.
+    pub fn sample(&mut self, byte_rate: usize) -> usize {
+        self.rates.push_back(byte_rate);
+        while self.rates.len() > RATE_WINDOW {
+            self.rates.pop_front();
+        }
+        self.rate()
+    }
+
+    /// The mean of the stream's last few sampled rates in bytes per
+    /// second, 0 if it has not been sampled since it started.
+    pub fn rate(&self) -> usize {
+        self.rates
+            .iter()
+            .sum::<usize>()
+            .checked_div(self.rates.len())
+            .unwrap_or(0)
+    }
+
+    /// Forgets the previous stream's rates and warnings as a new one has
+    /// started.
+    pub fn reset(&mut self) {
+        self.rates.clear();
+        self.warned_at.clear();
+    }
+
+    /// Checks a rate against one of the stream's ceilings, returning a
+    /// violation when it is over and has not been warned about it in the
+    /// last `BANDWIDTH_WARNING_INTERVAL` seconds.
+    ///
+    /// A stream that drops back under is warned straight away if it goes
+    /// over again.
+    pub fn check(
+        &mut self,
+        scope: LimitScope,
+        byte_rate: usize,
+        limit: usize,
+    ) -> Option<Violation> {
+        if byte_rate <= limit {
+            self.warned_at.remove(&scope);
+            return None
+        }
+
+        let now = utils::now_millis();
+        let interval = config::get().bandwidth_warning_interval * 1000;
+        if let Some(warned_at) = self.warned_at.get(&scope) {
+            if now.saturating_sub(*warned_at) < interval {
+                return None
+            }
+        }
+
+        self.warned_at.insert(scope, now);
+        Some(Violation {
+            at: now,
+            scope,
+            byte_rate,
+            limit,
+            action: config::get().bandwidth_limit_endpoint
+                .as_ref()
+                .map(|_| config::get().bandwidth_limit_action),
+            error: None,
+        })
+    }
+
+    /// Forgets that the stream was over a ceiling.
+    pub fn clear(&mut self, scope: LimitScope) {
+        self.warned_at.remove(&scope);
+    }
+
+    /// Records a violation once the live server has been asked to act on it.
+    pub fn record(&mut self, violation: Violation) {
+        self.violations += 1;
+        self.recent.push_back(violation);
+        while self.recent.len() > VIOLATION_HISTORY {
+            self.recent.pop_front();
+        }
+    }
+}
+
+
+/// Asks the room's live server to throttle or kick the publisher with
+/// `BANDWIDTH_LIMIT_ENDPOINT`.
+///
+/// The endpoint is sent `{"room_id", "action", "scope", "byte_rate",
+/// "limit"}` with the api key the same way as the stats api, any 2xx
+/// counts as done.
+pub async fn enforce(
+    live_server: &str,
+    room_id: &str,
+    violation: &Violation,
+) -> Result<(), String> {
+    let (endpoint, action) = match (&config::get().bandwidth_limit_endpoint, violation.action) {
+        (Some(endpoint), Some(action)) => (endpoint, action),
+        _ => return Ok(()),
+    };
+
+    let url = format!("{}{}", live_server, endpoint.replace("{room_id}", room_id));
+    let resp = CLIENT
+        .post(&url)
+        .query(&[("authorization", config::get().api_key.as_str())])
+        .json(&serde_json::json!({
+            "room_id": room_id,
+            "action": action,
+            "scope": violation.scope,
+            "byte_rate": violation.byte_rate,
+            "limit": violation.limit,
+        }))
+        .timeout(LIMIT_REQUEST_TIMEOUT)
+        .send()
+        .await
+        .map_err(|e| e.to_string())?;
+
+    let status = resp.status();
+    if status.is_success() {
+        Ok(())
+    } else {
+        Err(format!("unexpected status: {}", status.as_u16()))
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn state() -> BandwidthState {
+        config::init_default();
+        BandwidthState::default()
+    }
+
+    /// Pretends the stream was last warned about the ceiling `ms` earlier.
+    fn rewind(state: &mut BandwidthState, scope: LimitScope, ms: u64) {
+        if let Some(warned_at) = state.warned_at.get_mut(&scope) {
+            *warned_at -= ms;
+        }
+    }
+
+    fn interval() -> u64 {
+        config::get().bandwidth_warning_interval * 1000
+    }
+
+    #[test]
+    fn rate_is_the_mean_of_the_last_few_samples() {
+        let mut state = state();
+        assert_eq!(state.rate(), 0);
+        assert_eq!(state.sample(100), 100);
+        assert_eq!(state.sample(300), 200);
+
+        for rate in [400, 500, 600, 700, 800] {
+            state.sample(rate);
+        }
+        assert_eq!(state.rate(), 600);
+        assert_eq!(state.rates.len(), RATE_WINDOW);
+    }
+
+    #[test]
+    fn reset_forgets_the_previous_stream() {
+        let mut state = state();
+        state.sample(500);
+        assert!(state.check(LimitScope::Room, 500, 100).is_some());
+
+        state.reset();
+        assert_eq!(state.rate(), 0);
+        assert!(state.check(LimitScope::Room, 500, 100).is_some());
+    }
+
+    #[test]
+    fn rate_under_the_limit_is_fine() {
+        let mut state = state();
+        assert!(state.check(LimitScope::Room, 100, 100).is_none());
+        assert!(state.check(LimitScope::Room, 0, 100).is_none());
+    }
+
+    #[test]
+    fn rate_over_the_limit_is_warned_once_per_interval() {
+        let mut state = state();
+
+        let violation = state.check(LimitScope::Room, 200, 100).unwrap();
+        assert_eq!(violation.scope, LimitScope::Room);
+        assert_eq!(violation.byte_rate, 200);
+        assert_eq!(violation.limit, 100);
+        assert_eq!(violation.action, None);
+
+        assert!(state.check(LimitScope::Room, 300, 100).is_none());
+
+        rewind(&mut state, LimitScope::Room, interval() - 1_000);
+        assert!(state.check(LimitScope::Room, 300, 100).is_none());
+
+        rewind(&mut state, LimitScope::Room, 1_000);
+        assert!(state.check(LimitScope::Room, 300, 100).is_some());
+    }
+
+    #[test]
+    fn rate_dropping_under_the_limit_clears_the_warning() {
+        let mut state = state();
+        assert!(state.check(LimitScope::Room, 200, 100).is_some());
+        assert!(state.check(LimitScope::Room, 50, 100).is_none());
+        assert!(state.warned_at.is_empty());
+        assert!(state.check(LimitScope::Room, 200, 100).is_some());
+
+        state.clear(LimitScope::Room);
+        assert!(state.check(LimitScope::Room, 200, 100).is_some());
+    }
+
+    #[test]
+    fn ceilings_are_warned_about_separately() {
+        let mut state = state();
+        assert!(state.check(LimitScope::Room, 200, 100).is_some());
+        assert!(state.check(LimitScope::Global, 900, 600).is_some());
+        assert!(state.check(LimitScope::Room, 200, 100).is_none());
+        assert!(state.check(LimitScope::Global, 900, 600).is_none());
+    }
+
+    #[test]
+    fn only_recent_violations_are_kept() {
+        let mut state = state();
+        for limit in 0..VIOLATION_HISTORY + 5 {
+            state.warned_at.clear();
+            let violation = state.check(LimitScope::Room, usize::MAX, limit).unwrap();
+            state.record(violation);
+        }
+
+        assert_eq!(state.violations, VIOLATION_HISTORY + 5);
+        assert_eq!(state.recent.len(), VIOLATION_HISTORY);
+        assert_eq!(state.recent[0].limit, 5);
+    }
+}
@@ -0,0 +1,606 @@
+use serde::{Serialize, Deserialize, Deserializer};
+
+use std::env;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::net::SocketAddr;
+use std::path::Path;
+use std::sync::OnceLock;
+
+use crate::bandwidth::LimitAction;
+use crate::logging::{self, LogFormat};
+use crate::store::StoreBackend;
+
+/// The config file read if no other is given and it exists.
+const DEFAULT_CONFIG_PATH: &str = "gateway.toml";
+
+/// The environment variable a config file can be given with.
+const CONFIG_PATH_VAR: &str = "GATEWAY_CONFIG";
+
+static CONFIG: OnceLock<Config> = OnceLock::new();
+
+/// Settings that can not be given as command line flags as anyone on the
+/// host can read a process's arguments.
+const SECRET_SETTINGS: &[&str] = &["admin_api_keys", "webhook_keys", "api_key", "auth_secret"];
+
+
+/// Every setting of the gateway.
+///
+/// The settings are loaded in layers each overriding the last, first the
+/// defaults, then the TOML file given with `--config <path>` or
+/// `GATEWAY_CONFIG` (`gateway.toml` if it exists and neither is given),
+/// then environment variables named after the setting in upper case e.g.
+/// `BROADCAST_CAPACITY` and finally command line flags named after the
+/// setting e.g. `--broadcast-capacity 100`. Keys and secrets are only read
+/// from the file and environment.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// The address the gateway listens on.
+    pub bind: SocketAddr,
+
+    /// The public url of the websocket gateway, only used for logging.
+    pub public_gateway_url: String,
+
+    /// The public url of the api, only used for logging.
+    pub public_api_url: String,
+
+    /// The `Access-Control-Allow-Origin` header sent by the emit route.
+    pub cors_allow_origin: String,
+
+    /// The `Access-Control-Allow-Methods` header sent by the emit route.
+    pub cors_allow_methods: String,
+
+    /// The `Access-Control-Allow-Headers` header sent by the emit route.
+    pub cors_allow_headers: String,
+
+    /// The keys that are allowed to use the admin api.
+    ///
+    /// From the environment this is a comma separated list so old and new
+    /// keys can both be accepted while rotating them.
+    #[serde(deserialize_with = "key_list")]
+    pub admin_api_keys: Vec<String>,
+
+    /// The keys the live server can pass as `?key=` when calling the
+    /// webhooks, given the same way as `admin_api_keys`.
+    #[serde(deserialize_with = "key_list")]
+    pub webhook_keys: Vec<String>,
+
+    /// The key sent to the live server's stats api.
+    pub api_key: String,
+
+    /// The max size in bytes of a single frame sent by a client, the
+    /// connection is dropped if it sends a bigger one.
+    pub max_frame_size: usize,
+
+    /// The max amount of characters in a chat message.
+    pub max_chat_length: usize,
+
+    /// The amount of messages a connection may send per second.
+    pub message_rate: f64,
+
+    /// The amount of messages a connection may send in one burst before
+    /// being limited to `message_rate`.
+    pub message_burst: f64,
+
+    /// How often in seconds clients should heartbeat and are sent pings.
+    pub heartbeat_interval: u64,
+
+    /// The seconds a client can go without heartbeating before it is
+    /// considered dead and disconnected.
+    pub heartbeat_timeout: u64,
+
+    /// The amount of events each room's broadcast channel holds before
+    /// slow clients start missing them.
+    pub broadcast_capacity: usize,
+
+    /// What to do with a client that falls behind the broadcast channel.
+    pub lag_policy: LagPolicy,
+
+    /// How often in seconds a live room's stats are polled.
+    pub live_poll_interval: u64,
+
+    /// How often in seconds a room that is not live is polled to see if
+    /// it has gone live.
+    pub idle_poll_interval: u64,
+
+    /// The amount of failed polls before a stats watcher gives up and is
+    /// left to the supervisor to restart.
+    pub max_poll_errors: usize,
+
+    /// How often in seconds the supervisor checks on the stats watchers.
+    pub supervisor_interval: u64,
+
+    /// The seconds before a stats watcher's first restart, this doubles
+    /// for every failed restart.
+    pub watcher_backoff_base: u64,
+
+    /// The most seconds to wait before restarting a stats watcher.
+    pub watcher_backoff_max: u64,
+
+    /// The seconds pushed stats are trusted for before a room using the
+    /// push stats backend is treated as not live.
+    pub push_stats_timeout: u64,
+
+    /// The seconds after a webhook from the live server during which the
+    /// stats watcher's polls are not trusted to say if the room is live,
+    /// once the webhooks stop polling takes back over.
+    pub webhook_timeout: u64,
+
+    /// The seconds a dropped session can be resumed for before the user
+    /// is removed from the room.
+    pub resume_grace: u64,
+
+    /// The amount of recent events each room keeps to replay on resume.
+    pub resume_buffer_size: usize,
+
+    /// The seconds a client has to identify after connecting.
+    pub identify_timeout: u64,
+
+    /// Where rooms are persisted between restarts.
+    pub store_backend: StoreBackend,
+
+    /// The file rooms are saved to by the file store.
+    pub store_path: String,
+
+    /// How often in seconds rooms are saved to the store.
+    pub store_interval: u64,
+
+    /// The seconds clients are given to disconnect on their own when the
+    /// gateway is shutting down before they are closed.
+    pub shutdown_drain: u64,
+
+    /// The seconds clients are told to wait before reconnecting when the
+    /// gateway is shutting down, each room is given up to this again as
+    /// jitter so they do not all reconnect at once.
+    pub reconnect_delay: u64,
+
+    /// The shared secret session tokens are signed with.
+    pub auth_secret: Option<String>,
+
+    /// The endpoint session tokens are sent to for validation, this
+    /// takes priority over `auth_secret` if both are set.
+    pub auth_endpoint: Option<String>,
+
+    /// The seconds the auth endpoint is given to answer, a client that
+    /// is not answered in time fails to identify.
+    pub auth_endpoint_timeout: u64,
+
+    /// The XP a viewer earns for each minute they watch a live stream at
+    /// a 1x multiplier.
+    pub xp_per_minute: f64,
+
+    /// The amount of recent XP payout batches kept so retried payout
+    /// requests get the same batch back.
+    pub payout_history: usize,
+
+    /// The amount of stats samples each room keeps for its history, a
+    /// day's worth at the default `LIVE_POLL_INTERVAL`.
+    pub stats_history_size: usize,
+
+    /// The most bytes per second a room's stream may average over its last
+    /// few samples, 0 for no limit, rooms can be given their own limit
+    /// when created.
+    pub room_byte_rate_limit: usize,
+
+    /// The most bytes per second every live room's stream may average over
+    /// its last few samples together, 0 for no limit. Once over the biggest streams are warned
+    /// until the rest fit under it.
+    pub global_byte_rate_limit: usize,
+
+    /// How often in seconds a stream that stays over a limit is warned
+    /// and acted on again.
+    pub bandwidth_warning_interval: u64,
+
+    /// The path on a room's live server called when its stream goes over
+    /// a limit, `{room_id}` is replaced with the room's id. If not set
+    /// clients are only warned.
+    pub bandwidth_limit_endpoint: Option<String>,
+
+    /// What the live server is asked to do with a publisher over a limit.
+    pub bandwidth_limit_action: LimitAction,
+
+    /// How long in seconds a live server found reachable or not by the
+    /// readiness check is trusted before it is probed again.
+    pub live_server_check_ttl: u64,
+
+    /// The seconds the readiness check waits for a live server to answer.
+    pub live_server_check_timeout: u64,
+
+    /// How log lines are written, can be changed at runtime through the
+    /// `/logging` admin route.
+    pub log_format: LogFormat,
+
+    /// The `EnvFilter` directives deciding which levels are logged for
+    /// each module e.g. `info,gateway::ws=debug`, can be changed at
+    /// runtime the same way as `log_format`.
+    pub log_filter: String,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            bind: SocketAddr::from(([0, 0, 0, 0], 3030)),
+            public_gateway_url: "wss://gateway.spooderfy.com/ws".to_string(),
+            public_api_url: "https://gateway.spooderfy.com".to_string(),
+            cors_allow_origin: "*".to_string(),
+            cors_allow_methods: "PUT".to_string(),
+            cors_allow_headers: "*".to_string(),
+            admin_api_keys: Vec::new(),
+            webhook_keys: Vec::new(),
+            api_key: String::new(),
+            max_frame_size: 4096,
+            max_chat_length: 500,
+            message_rate: 2.0,
+            message_burst: 5.0,
+            heartbeat_interval: 30,
+            heartbeat_timeout: 75,
+            broadcast_capacity: 50,
+            lag_policy: LagPolicy::Resync,
+            live_poll_interval: 60,
+            idle_poll_interval: 10,
+            max_poll_errors: 3,
+            supervisor_interval: 5,
+            watcher_backoff_base: 5,
+            watcher_backoff_max: 300,
+            push_stats_timeout: 120,
+            webhook_timeout: 120,
+            resume_grace: 30,
+            resume_buffer_size: 256,
+            identify_timeout: 10,
+            store_backend: StoreBackend::File,
+            store_path: "rooms.json".to_string(),
+            store_interval: 30,
+            shutdown_drain: 15,
+            reconnect_delay: 5,
+            auth_secret: None,
+            auth_endpoint: None,
+            auth_endpoint_timeout: 5,
+            xp_per_minute: 1.0,
+            payout_history: 100,
+            stats_history_size: 1440,
+            room_byte_rate_limit: 0,
+            global_byte_rate_limit: 0,
+            bandwidth_warning_interval: 60,
+            bandwidth_limit_endpoint: None,
+            bandwidth_limit_action: LimitAction::Throttle,
+            live_server_check_ttl: 30,
+            live_server_check_timeout: 5,
+            log_format: LogFormat::Pretty,
+            log_filter: "info".to_string(),
+        }
+    }
+}
+
+impl Config {
+    /// Checks the settings make sense together, returning every problem.
+    fn validate(&self) -> Result<(), Vec<String>> {
+        let mut problems = Vec::new();
+        let mut check = |ok: bool, problem: &str| {
+            if !ok {
+                problems.push(problem.to_string());
+            }
+        };
+
+        check(self.max_frame_size > 0, "max_frame_size must be above 0");
+        check(self.max_chat_length > 0, "max_chat_length must be above 0");
+        check(self.message_rate > 0.0, "message_rate must be above 0");
+        check(self.message_burst >= 1.0, "message_burst must be at least 1");
+        check(self.heartbeat_interval > 0, "heartbeat_interval must be above 0");
+        check(
+            self.heartbeat_timeout > self.heartbeat_interval,
+            "heartbeat_timeout must be longer than heartbeat_interval",
+        );
+        check(self.broadcast_capacity > 0, "broadcast_capacity must be above 0");
+        check(self.live_poll_interval > 0, "live_poll_interval must be above 0");
+        check(self.idle_poll_interval > 0, "idle_poll_interval must be above 0");
+        check(self.max_poll_errors > 0, "max_poll_errors must be above 0");
+        check(self.supervisor_interval > 0, "supervisor_interval must be above 0");
+        check(self.watcher_backoff_base > 0, "watcher_backoff_base must be above 0");
+        check(
+            self.watcher_backoff_max >= self.watcher_backoff_base,
+            "watcher_backoff_max must be at least watcher_backoff_base",
+        );
+        check(self.resume_buffer_size > 0, "resume_buffer_size must be above 0");
+        check(self.identify_timeout > 0, "identify_timeout must be above 0");
+        check(self.auth_endpoint_timeout > 0, "auth_endpoint_timeout must be above 0");
+        check(self.store_interval > 0, "store_interval must be above 0");
+        check(
+            (self.store_backend != StoreBackend::File) | !self.store_path.is_empty(),
+            "store_path must be set for the file store",
+        );
+        check(self.xp_per_minute >= 0.0, "xp_per_minute can not be negative");
+        check(self.payout_history > 0, "payout_history must be above 0");
+        check(self.stats_history_size > 0, "stats_history_size must be above 0");
+        check(
+            self.bandwidth_warning_interval > 0,
+            "bandwidth_warning_interval must be above 0",
+        );
+        check(
+            self.bandwidth_limit_endpoint
+                .as_ref()
+                .map(|endpoint| endpoint.starts_with('/'))
+                .unwrap_or(true),
+            "bandwidth_limit_endpoint must be a path starting with /",
+        );
+        check(
+            self.live_server_check_timeout > 0,
+            "live_server_check_timeout must be above 0",
+        );
+        check(logging::check_filter(&self.log_filter), "log_filter is not a valid filter");
+
+        if problems.is_empty() {
+            Ok(())
+        } else {
+            Err(problems)
+        }
+    }
+}
+
+
+/// What to do with a client that falls too far behind a room's events.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LagPolicy {
+    /// Skip the client to the latest events and send it an `OP_RESYNC`
+    /// with the room's current state.
+    Resync,
+
+    /// Close the connection with `CLOSE_LAGGED`.
+    Disconnect,
+}
+
+
+/// The reasons the config can fail to load.
+#[derive(Debug)]
+pub enum ConfigError {
+    /// The command line arguments could not be understood.
+    Args(String),
+
+    /// The config file could not be read.
+    Read(String, io::Error),
+
+    /// A setting has the wrong type or does not exist.
+    Parse(toml::de::Error),
+
+    /// The settings do not make sense.
+    Invalid(Vec<String>),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Args(e) => write!(f, "{}", e),
+            Self::Read(path, e) => write!(f, "could not read {}: {}", path, e),
+            Self::Parse(e) => write!(f, "{}", e),
+            Self::Invalid(problems) => write!(f, "{}", problems.join(", ")),
+        }
+    }
+}
+
+
+/// Loads and validates the config from the config file, environment and
+/// command line, this must be called before anything reads the config.
+pub fn init() -> Result<(), ConfigError> {
+    let config = load(env::args().skip(1).collect())?;
+    config.validate().map_err(ConfigError::Invalid)?;
+
+    let _ = CONFIG.set(config);
+    Ok(())
+}
+
+
+/// Uses the default config, for unit tests of code that reads it.
+#[cfg(test)]
+pub fn init_default() {
+    let _ = CONFIG.set(Config::default());
+}
+
+
+/// The gateway's config.
+pub fn get() -> &'static Config {
+    CONFIG.get().expect("config::init must be called before the config is read")
+}
+
+
+/// Merges every layer of settings and parses them into the config.
+fn load(args: Vec<String>) -> Result<Config, ConfigError> {
+    let defaults = match serde_json::to_value(Config::default()) {
+        Ok(serde_json::Value::Object(defaults)) => defaults,
+        _ => unreachable!("the config always serializes to a map"),
+    };
+
+    let args = parse_args(args, &defaults)?;
+
+    let config_path = args.config_path
+        .or_else(|| env::var(CONFIG_PATH_VAR).ok().filter(|path| !path.is_empty()));
+    let mut table = match config_path {
+        Some(path) => read_file(&path)?,
+        None if Path::new(DEFAULT_CONFIG_PATH).exists() => read_file(DEFAULT_CONFIG_PATH)?,
+        None => toml::Table::new(),
+    };
+
+    for (key, default) in defaults.iter() {
+        // An empty variable is treated the same as a missing one.
+        let value = env::var(key.to_uppercase()).ok().filter(|value| !value.is_empty());
+        if let Some(value) = value {
+            table.insert(key.clone(), override_value(default, &value));
+        }
+    }
+
+    for (key, value) in args.settings {
+        let value = override_value(&defaults[&key], &value);
+        table.insert(key, value);
+    }
+
+    // Round tripping through the text form gives errors that point at
+    // the setting that is wrong.
+    let merged = toml::to_string(&table).expect("a toml table always serializes");
+    toml::from_str(&merged).map_err(ConfigError::Parse)
+}
+
+
+/// Reads a TOML config file.
+fn read_file(path: &str) -> Result<toml::Table, ConfigError> {
+    let contents = fs::read_to_string(path)
+        .map_err(|e| ConfigError::Read(path.to_string(), e))?;
+
+    toml::from_str(&contents).map_err(ConfigError::Parse)
+}
+
+
+/// What was given on the command line.
+struct Args {
+    /// The config file to read.
+    config_path: Option<String>,
+
+    /// The settings given as `(key, value)`.
+    settings: Vec<(String, String)>,
+}
+
+
+/// Splits the command line into the config file path and the settings
+/// given as `--some-setting <value>` or `--some-setting=<value>`.
+fn parse_args(
+    args: Vec<String>,
+    defaults: &serde_json::Map<String, serde_json::Value>,
+) -> Result<Args, ConfigError> {
+    let mut config_path = None;
+    let mut settings = Vec::new();
+    let mut args = args.into_iter();
+
+    while let Some(arg) = args.next() {
+        if (arg == "--help") | (arg == "-h") {
+            print_usage(defaults);
+            std::process::exit(0);
+        }
+
+        let flag = arg
+            .strip_prefix("--")
+            .ok_or_else(|| ConfigError::Args(format!("unexpected argument {:?}", arg)))?;
+
+        let (name, value) = match flag.split_once('=') {
+            Some((name, value)) => (name.to_string(), value.to_string()),
+            None => {
+                let value = args.next().ok_or_else(|| {
+                    ConfigError::Args(format!("--{} needs a value", flag))
+                })?;
+                (flag.to_string(), value)
+            },
+        };
+
+        if name == "config" {
+            config_path = Some(value);
+            continue;
+        }
+
+        let key = name.replace('-', "_");
+        if !defaults.contains_key(&key) {
+            return Err(ConfigError::Args(format!("unknown setting --{}", name)))
+        }
+
+        if SECRET_SETTINGS.contains(&key.as_str()) {
+            return Err(ConfigError::Args(format!(
+                "--{} can not be given on the command line, set {} or {} in the config file",
+                name,
+                key.to_uppercase(),
+                key,
+            )))
+        }
+
+        settings.push((key, value));
+    }
+
+    Ok(Args {
+        config_path,
+        settings,
+    })
+}
+
+
+/// Prints how to run the gateway and every setting it takes.
+fn print_usage(defaults: &serde_json::Map<String, serde_json::Value>) {
+    println!("Usage: gateway [--config <path>] [--<setting> <value>]...");
+    println!();
+    println!("Settings, also read from the config file and environment:");
+
+    for (key, default) in defaults.iter() {
+        if SECRET_SETTINGS.contains(&key.as_str()) {
+            continue
+        }
+
+        println!("    --{:<24} {}", key.replace('_', "-"), default);
+    }
+}
+
+
+/// Turns a setting given as text into a TOML value, settings that are
+/// numbers or bools by default are parsed as such and anything else is
+/// kept as a string.
+fn override_value(default: &serde_json::Value, value: &str) -> toml::Value {
+    let is_scalar = matches!(default, serde_json::Value::Number(_) | serde_json::Value::Bool(_));
+
+    if is_scalar {
+        let parsed = toml::from_str::<toml::Table>(&format!("value = {}", value))
+            .ok()
+            .and_then(|mut table| table.remove("value"));
+
+        if let Some(parsed) = parsed {
+            return parsed
+        }
+    }
+
+    toml::Value::String(value.to_string())
+}
+
+
+/// Reads a list of keys either as a list or a comma separated string.
+fn key_list<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<String>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Keys {
+        List(Vec<String>),
+        Joined(String),
+    }
+
+    let keys = match Keys::deserialize(deserializer)? {
+        Keys::List(keys) => keys,
+        Keys::Joined(keys) => keys.split(',').map(str::to_string).collect(),
+    };
+
+    Ok(keys
+        .into_iter()
+        .map(|key| key.trim().to_string())
+        .filter(|key| !key.is_empty())
+        .collect())
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Args, ConfigError> {
+        let defaults = match serde_json::to_value(Config::default()) {
+            Ok(serde_json::Value::Object(defaults)) => defaults,
+            _ => unreachable!(),
+        };
+
+        parse_args(args.iter().map(|arg| arg.to_string()).collect(), &defaults)
+    }
+
+    #[test]
+    fn settings_are_read_from_flags() {
+        let args = parse(&["--config", "gw.toml", "--broadcast-capacity=100"]).unwrap();
+        assert_eq!(args.config_path.as_deref(), Some("gw.toml"));
+        assert_eq!(args.settings, vec![("broadcast_capacity".to_string(), "100".to_string())]);
+    }
+
+    #[test]
+    fn secrets_can_not_be_flags() {
+        for flag in ["--admin-api-keys", "--webhook-keys", "--api-key", "--auth-secret"] {
+            assert!(matches!(parse(&[flag, "secret"]), Err(ConfigError::Args(_))));
+        }
+    }
+}
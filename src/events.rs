@@ -0,0 +1,731 @@
+use serde::{Serialize, Serializer, Deserialize, Deserializer};
+use serde::ser::SerializeStruct;
+use serde::de::Error as DeError;
+use serde_json::Value;
+
+use crate::analytics::ViewerStats;
+use crate::bandwidth::{LimitAction, LimitScope};
+use crate::managers::BasicStats;
+use crate::identity::UserProfile;
+use crate::lifecycle::Lifecycle;
+use crate::opcodes::{self, OpCode};
+
+/// The gateway protocol version this build speaks by default.
+pub const PROTOCOL_VERSION: u8 = 1;
+
+/// Every protocol version a client is allowed to ask for.
+pub const SUPPORTED_VERSIONS: &[u8] = &[1];
+
+
+/// The payload sent when a room's stream has been published but is not
+/// watchable yet.
+#[derive(Debug, Clone, Serialize)]
+pub struct LiveStarting {
+    /// When the stream was published as a unix timestamp in ms.
+    pub started_at: u64,
+}
+
+
+/// The payload sent when a room's stream becomes watchable.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LiveReady {
+    /// The HLS playlist url to watch the stream from.
+    pub stream_url: String,
+
+    /// When the stream was published as a unix timestamp in ms.
+    #[serde(default)]
+    pub started_at: u64,
+
+    /// When the stream became watchable as a unix timestamp in ms, this
+    /// is later than `started_at` if it recovered from stalling.
+    #[serde(default)]
+    pub live_at: u64,
+}
+
+
+/// The payload sent when a room's stream stops receiving data while it
+/// is still published.
+#[derive(Debug, Clone, Serialize)]
+pub struct LiveStalled {
+    /// When the stream was published as a unix timestamp in ms.
+    pub started_at: u64,
+
+    /// When the stream stalled as a unix timestamp in ms.
+    pub stalled_at: u64,
+}
+
+
+/// The payload sent when a room's stream stops being watchable.
+#[derive(Debug, Clone, Serialize)]
+pub struct LiveEnded {
+    /// Why the stream ended e.g. 'unpublished' or 'stats_unavailable'.
+    pub reason: String,
+
+    /// When the stream was published as a unix timestamp in ms.
+    pub started_at: Option<u64>,
+
+    /// When the stream ended as a unix timestamp in ms.
+    pub ended_at: u64,
+
+    /// Who watched the stream, None if it was not counted.
+    pub viewers: Option<ViewerStats>,
+}
+
+
+/// The content of a message sent by a client.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum MessageContent {
+    /// A plain text chat message.
+    Chat {
+        content: String,
+    },
+
+    /// A single emoji reaction.
+    Reaction {
+        emoji: String,
+    },
+
+    /// An app defined event, the gateway only checks the name.
+    Custom {
+        name: String,
+
+        #[serde(default)]
+        data: Value,
+    },
+}
+
+
+/// Who sent a client message.
+#[derive(Debug, Clone, Serialize)]
+pub struct MessageSender {
+    /// The id of the connection the message came from.
+    pub connection_id: u64,
+
+    /// The user the connection identified as.
+    pub user: UserProfile,
+}
+
+
+/// A client message after it has been validated and stamped by the gateway.
+#[derive(Debug, Clone, Serialize)]
+pub struct ClientMessage {
+    /// Who sent the message.
+    pub sender: MessageSender,
+
+    /// When the gateway received the message as a unix timestamp in ms.
+    pub timestamp: u64,
+
+    /// The message itself.
+    #[serde(flatten)]
+    pub content: MessageContent,
+}
+
+
+/// The payload sent to a single client when it sends something the
+/// gateway refuses.
+#[derive(Debug, Clone, Serialize)]
+pub struct InvalidFrame {
+    /// A short machine readable reason e.g. 'rate_limited'.
+    pub code: &'static str,
+
+    /// A human readable description of what went wrong.
+    pub message: String,
+}
+
+
+/// The payload sent to a client as soon as it connects.
+#[derive(Debug, Clone, Serialize)]
+pub struct Hello {
+    /// How often the client should heartbeat in milliseconds.
+    pub heartbeat_interval: u64,
+
+    /// The protocol version the connection is using.
+    pub version: u8,
+}
+
+
+/// The payload sent to a client once it has identified.
+#[derive(Debug, Clone, Serialize)]
+pub struct Ready {
+    /// The id given to this connection.
+    pub connection_id: u64,
+
+    /// The id to resume this session with if the connection drops.
+    pub session_id: String,
+
+    /// The user the connection identified as.
+    pub user: UserProfile,
+}
+
+
+/// The payload sent to a client once its session has been resumed.
+#[derive(Debug, Clone, Serialize)]
+pub struct Resumed {
+    /// The id of the resumed session.
+    pub session_id: String,
+
+    /// The amount of missed events being replayed.
+    pub replayed: usize,
+}
+
+
+/// The payload sent to a client that fell too far behind the room's
+/// events, replacing everything it missed with the room's current state.
+#[derive(Debug, Clone, Serialize)]
+pub struct Resync {
+    /// The seq of the last event covered by this snapshot, the next
+    /// event the client receives follows on from it.
+    pub seq: u64,
+
+    /// The amount of events the client missed.
+    pub skipped: u64,
+
+    /// The room's current stats.
+    pub stats: BasicStats,
+
+    /// Every unique user connected to the room.
+    pub members: Vec<UserProfile>,
+
+    /// Where to watch the stream if the room is live.
+    pub live: Option<LiveReady>,
+
+    /// The state of the room's stream.
+    pub stream: Lifecycle,
+}
+
+
+/// The payload giving a client every user in the room.
+#[derive(Debug, Clone, Serialize)]
+pub struct PresenceSnapshot {
+    /// Every unique user connected to the room.
+    pub members: Vec<UserProfile>,
+}
+
+
+/// The payload telling clients the gateway is going away.
+#[derive(Debug, Clone, Serialize)]
+pub struct Reconnect {
+    /// How long in ms the client should wait before reconnecting.
+    pub delay: u64,
+}
+
+
+/// The payload sent when a room's stream goes over a bandwidth ceiling.
+#[derive(Debug, Clone, Serialize)]
+pub struct BandwidthWarning {
+    /// Which ceiling the stream went over.
+    pub scope: LimitScope,
+
+    /// The stream's rate over its last few samples in bytes per second,
+    /// or every live stream's combined rate for the global ceiling.
+    pub byte_rate: usize,
+
+    /// The ceiling in bytes per second.
+    pub limit: usize,
+
+    /// What the live server is being asked to do with the publisher,
+    /// None if it is only a warning.
+    pub action: Option<LimitAction>,
+}
+
+
+/// The payload a client identifies itself with.
+#[derive(Debug, Clone, Deserialize)]
+pub struct Identify {
+    /// The signed session token issued by the main app.
+    pub token: String,
+}
+
+
+/// The payload a client resumes a dropped session with.
+#[derive(Debug, Clone, Deserialize)]
+pub struct Resume {
+    /// The signed session token issued by the main app.
+    pub token: String,
+
+    /// The session id given in the ready event.
+    pub session_id: String,
+
+    /// The last sequence number the client received.
+    pub seq: u64,
+}
+
+
+/// Every event that can be sent down the gateway.
+///
+/// This is the one place the wire format lives, each variant carries
+/// the payload for its opcode and is always sent as:
+///
+/// `{"opcode": <opcode>, "payload": <payload>}`
+#[derive(Debug, Clone)]
+pub enum GatewayEvent {
+    /// The room's member count or multiplier has changed.
+    StatsUpdate(BasicStats),
+
+    /// The room's stream has been published and is starting.
+    LiveStarting(LiveStarting),
+
+    /// The room's stream has started and can be watched.
+    LiveReady(LiveReady),
+
+    /// The room's stream has stopped receiving data.
+    LiveStalled(LiveStalled),
+
+    /// The room's stream has ended.
+    LiveEnded(LiveEnded),
+
+    /// A message pushed to the room by the backend.
+    Message(Value),
+
+    /// A message sent by a client in the room.
+    ClientMessage(ClientMessage),
+
+    /// A frame sent by this client was refused.
+    Invalid(InvalidFrame),
+
+    /// This client has identified and joined the room.
+    Ready(Ready),
+
+    /// Every user in the room, sent to a client when it joins.
+    PresenceSnapshot(PresenceSnapshot),
+
+    /// A user has joined the room.
+    PresenceJoin(UserProfile),
+
+    /// A user has left the room.
+    PresenceLeave(UserProfile),
+
+    /// The first event sent to a client telling it how to heartbeat.
+    Hello(Hello),
+
+    /// The client's heartbeat was received.
+    HeartbeatAck,
+
+    /// The client's session was resumed, the missed events follow.
+    Resumed(Resumed),
+
+    /// The client fell behind and is being given the room's current state.
+    Resync(Resync),
+
+    /// The gateway is shutting down and clients should reconnect.
+    Reconnect(Reconnect),
+
+    /// The room's stream is using more bandwidth than it is allowed.
+    BandwidthWarning(BandwidthWarning),
+}
+
+impl GatewayEvent {
+    /// The opcode this event is sent with.
+    pub fn opcode(&self) -> OpCode {
+        match self {
+            Self::StatsUpdate(_) => opcodes::OP_STATS_UPDATE,
+            Self::LiveStarting(_) => opcodes::OP_LIVE_STARTING,
+            Self::LiveReady(_) => opcodes::OP_LIVE_READY,
+            Self::LiveStalled(_) => opcodes::OP_LIVE_STALLED,
+            Self::LiveEnded(_) => opcodes::OP_LIVE_ENDED,
+            Self::Message(_) => opcodes::OP_MESSAGE,
+            Self::ClientMessage(_) => opcodes::OP_MESSAGE,
+            Self::Invalid(_) => opcodes::OP_INVALID,
+            Self::Ready(_) => opcodes::OP_READY,
+            Self::PresenceSnapshot(_) => opcodes::OP_PRESENCE_SNAPSHOT,
+            Self::PresenceJoin(_) => opcodes::OP_PRESENCE_JOIN,
+            Self::PresenceLeave(_) => opcodes::OP_PRESENCE_LEAVE,
+            Self::Hello(_) => opcodes::OP_HELLO,
+            Self::HeartbeatAck => opcodes::OP_HEARTBEAT_ACK,
+            Self::Resumed(_) => opcodes::OP_RESUMED,
+            Self::Resync(_) => opcodes::OP_RESYNC,
+            Self::Reconnect(_) => opcodes::OP_RECONNECT,
+            Self::BandwidthWarning(_) => opcodes::OP_BANDWIDTH_WARNING,
+        }
+    }
+
+    /// Serializes the event into the text frame sent to clients.
+    pub fn to_frame(&self) -> String {
+        // The payloads are all plain structs and values so this wont fail.
+        serde_json::to_string(self).unwrap()
+    }
+
+    /// Serializes the event into a text frame carrying the room sequence
+    /// number it was broadcast with.
+    pub fn to_sequenced_frame(&self, seq: u64) -> String {
+        serde_json::to_string(&Frame { event: self, seq: Some(seq) }).unwrap()
+    }
+}
+
+impl Serialize for GatewayEvent {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        Frame { event: self, seq: None }.serialize(serializer)
+    }
+}
+
+
+/// An event as it is written to the wire.
+///
+/// Events broadcast to a room also carry their sequence number as `seq`,
+/// events sent to a single client do not.
+struct Frame<'a> {
+    event: &'a GatewayEvent,
+    seq: Option<u64>,
+}
+
+impl Serialize for Frame<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let len = if self.seq.is_some() { 3 } else { 2 };
+        let mut frame = serializer.serialize_struct("GatewayEvent", len)?;
+        frame.serialize_field("opcode", &self.event.opcode())?;
+
+        match self.event {
+            GatewayEvent::StatsUpdate(payload) => frame.serialize_field("payload", payload)?,
+            GatewayEvent::LiveStarting(payload) => frame.serialize_field("payload", payload)?,
+            GatewayEvent::LiveReady(payload) => frame.serialize_field("payload", payload)?,
+            GatewayEvent::LiveStalled(payload) => frame.serialize_field("payload", payload)?,
+            GatewayEvent::LiveEnded(payload) => frame.serialize_field("payload", payload)?,
+            GatewayEvent::Message(payload) => frame.serialize_field("payload", payload)?,
+            GatewayEvent::ClientMessage(payload) => frame.serialize_field("payload", payload)?,
+            GatewayEvent::Invalid(payload) => frame.serialize_field("payload", payload)?,
+            GatewayEvent::Ready(payload) => frame.serialize_field("payload", payload)?,
+            GatewayEvent::PresenceSnapshot(payload) => frame.serialize_field("payload", payload)?,
+            GatewayEvent::PresenceJoin(payload) => frame.serialize_field("payload", payload)?,
+            GatewayEvent::PresenceLeave(payload) => frame.serialize_field("payload", payload)?,
+            GatewayEvent::Hello(payload) => frame.serialize_field("payload", payload)?,
+            GatewayEvent::HeartbeatAck => frame.serialize_field("payload", &Value::Null)?,
+            GatewayEvent::Resumed(payload) => frame.serialize_field("payload", payload)?,
+            GatewayEvent::Resync(payload) => frame.serialize_field("payload", payload)?,
+            GatewayEvent::Reconnect(payload) => frame.serialize_field("payload", payload)?,
+            GatewayEvent::BandwidthWarning(payload) => frame.serialize_field("payload", payload)?,
+        };
+
+        if let Some(seq) = self.seq {
+            frame.serialize_field("seq", &seq)?;
+        }
+
+        frame.end()
+    }
+}
+
+/// Only the events the backend can emit to a room are decoded, every
+/// other opcode is the gateway's own and is refused.
+impl<'de> Deserialize<'de> for GatewayEvent {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let raw = RawFrame::deserialize(deserializer)?;
+
+        let event = match raw.opcode {
+            opcodes::OP_STATS_UPDATE => {
+                Self::StatsUpdate(serde_json::from_value(raw.payload).map_err(DeError::custom)?)
+            },
+            opcodes::OP_LIVE_READY => {
+                Self::LiveReady(serde_json::from_value(raw.payload).map_err(DeError::custom)?)
+            },
+            opcodes::OP_MESSAGE => Self::Message(raw.payload),
+            other => {
+                return Err(DeError::custom(format!("unknown opcode {}", other)))
+            },
+        };
+
+        Ok(event)
+    }
+}
+
+
+/// Every frame a client can send to the gateway.
+#[derive(Debug, Clone)]
+pub enum ClientEvent {
+    /// Send a message to everyone in the room.
+    SendMessage(MessageContent),
+
+    /// Identify the connection with a session token.
+    Identify(Identify),
+
+    /// Tell the gateway the client is still alive.
+    Heartbeat,
+
+    /// Resume a dropped session.
+    Resume(Resume),
+}
+
+impl<'de> Deserialize<'de> for ClientEvent {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let raw = RawFrame::deserialize(deserializer)?;
+
+        let event = match raw.opcode {
+            opcodes::OP_SEND_MESSAGE => {
+                Self::SendMessage(serde_json::from_value(raw.payload).map_err(DeError::custom)?)
+            },
+            opcodes::OP_IDENTIFY => {
+                Self::Identify(serde_json::from_value(raw.payload).map_err(DeError::custom)?)
+            },
+            opcodes::OP_HEARTBEAT => Self::Heartbeat,
+            opcodes::OP_RESUME => {
+                Self::Resume(serde_json::from_value(raw.payload).map_err(DeError::custom)?)
+            },
+            other => {
+                return Err(DeError::custom(format!("unknown opcode {}", other)))
+            },
+        };
+
+        Ok(event)
+    }
+}
+
+
+/// A frame that has not been matched to an event yet.
+#[derive(Deserialize)]
+struct RawFrame {
+    /// The frame opcode.
+    opcode: OpCode,
+
+    /// The frame payload, left as null if it is missing.
+    #[serde(default)]
+    payload: Value,
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn user() -> UserProfile {
+        UserProfile {
+            id: "1".to_string(),
+            display_name: "Ann".to_string(),
+            avatar: None,
+        }
+    }
+
+    fn user_json() -> Value {
+        json!({"id": "1", "display_name": "Ann", "avatar": null})
+    }
+
+    fn stats() -> BasicStats {
+        serde_json::from_value(json!({"members": 3, "multiplier": "1.2x"})).unwrap()
+    }
+
+    fn live_ready() -> LiveReady {
+        LiveReady {
+            stream_url: "http://live/live/a.m3u8".to_string(),
+            started_at: 1,
+            live_at: 2,
+        }
+    }
+
+    fn live_ready_json() -> Value {
+        json!({"stream_url": "http://live/live/a.m3u8", "started_at": 1, "live_at": 2})
+    }
+
+    /// Checks the event's frame with and without a sequence number.
+    fn assert_frame(event: GatewayEvent, opcode: OpCode, payload: Value) {
+        let frame: Value = serde_json::from_str(&event.to_frame()).unwrap();
+        assert_eq!(frame, json!({"opcode": opcode, "payload": payload}));
+
+        let frame: Value = serde_json::from_str(&event.to_sequenced_frame(7)).unwrap();
+        assert_eq!(frame, json!({"opcode": opcode, "payload": payload, "seq": 7}));
+    }
+
+    #[test]
+    fn frames_keep_their_field_order() {
+        let event = GatewayEvent::HeartbeatAck;
+        assert_eq!(event.to_frame(), r#"{"opcode":16,"payload":null}"#);
+        assert_eq!(event.to_sequenced_frame(7), r#"{"opcode":16,"payload":null,"seq":7}"#);
+    }
+
+    #[test]
+    fn stats_update_frame() {
+        let event = GatewayEvent::StatsUpdate(stats());
+        assert_frame(event, 0, json!({"members": 3, "multiplier": "1.2x"}));
+    }
+
+    #[test]
+    fn live_frames() {
+        let event = GatewayEvent::LiveStarting(LiveStarting { started_at: 1 });
+        assert_frame(event, 20, json!({"started_at": 1}));
+
+        assert_frame(GatewayEvent::LiveReady(live_ready()), 2, live_ready_json());
+
+        let event = GatewayEvent::LiveStalled(LiveStalled { started_at: 1, stalled_at: 3 });
+        assert_frame(event, 21, json!({"started_at": 1, "stalled_at": 3}));
+
+        let event = GatewayEvent::LiveEnded(LiveEnded {
+            reason: "unpublished".to_string(),
+            started_at: Some(1),
+            ended_at: 4,
+            viewers: Some(ViewerStats {
+                peak_viewers: 2,
+                peak_at: Some(2),
+                unique_viewers: 3,
+                joins: 1,
+                leaves: 1,
+                total_watch_time: 30,
+                average_watch_time: 10,
+            }),
+        });
+        assert_frame(event, 3, json!({
+            "reason": "unpublished",
+            "started_at": 1,
+            "ended_at": 4,
+            "viewers": {
+                "peak_viewers": 2,
+                "peak_at": 2,
+                "unique_viewers": 3,
+                "joins": 1,
+                "leaves": 1,
+                "total_watch_time": 30,
+                "average_watch_time": 10,
+            },
+        }));
+    }
+
+    #[test]
+    fn message_frames() {
+        assert_frame(GatewayEvent::Message(json!({"any": "thing"})), 5, json!({"any": "thing"}));
+
+        let event = GatewayEvent::ClientMessage(ClientMessage {
+            sender: MessageSender { connection_id: 9, user: user() },
+            timestamp: 5,
+            content: MessageContent::Chat { content: "hi".to_string() },
+        });
+        assert_frame(event, 5, json!({
+            "sender": {"connection_id": 9, "user": user_json()},
+            "timestamp": 5,
+            "type": "chat",
+            "content": "hi",
+        }));
+
+        let event = GatewayEvent::Invalid(InvalidFrame {
+            code: "rate_limited",
+            message: "Slow down!".to_string(),
+        });
+        assert_frame(event, 6, json!({"code": "rate_limited", "message": "Slow down!"}));
+    }
+
+    #[test]
+    fn connection_frames() {
+        let event = GatewayEvent::Hello(Hello { heartbeat_interval: 1000, version: 1 });
+        assert_frame(event, 10, json!({"heartbeat_interval": 1000, "version": 1}));
+
+        let event = GatewayEvent::Ready(Ready {
+            connection_id: 9,
+            session_id: "s".to_string(),
+            user: user(),
+        });
+        assert_frame(event, 12, json!({"connection_id": 9, "session_id": "s", "user": user_json()}));
+
+        assert_frame(GatewayEvent::HeartbeatAck, 16, Value::Null);
+
+        let event = GatewayEvent::Resumed(Resumed { session_id: "s".to_string(), replayed: 2 });
+        assert_frame(event, 18, json!({"session_id": "s", "replayed": 2}));
+
+        let event = GatewayEvent::Reconnect(Reconnect { delay: 500 });
+        assert_frame(event, 22, json!({"delay": 500}));
+    }
+
+    #[test]
+    fn resync_frame() {
+        let event = GatewayEvent::Resync(Resync {
+            seq: 4,
+            skipped: 2,
+            stats: stats(),
+            members: vec![user()],
+            live: Some(live_ready()),
+            stream: serde_json::from_value(json!({
+                "state": "live",
+                "changed_at": 2,
+                "started_at": 1,
+                "ended_at": null,
+                "end_reason": null,
+            })).unwrap(),
+        });
+        assert_frame(event, 19, json!({
+            "seq": 4,
+            "skipped": 2,
+            "stats": {"members": 3, "multiplier": "1.2x"},
+            "members": [user_json()],
+            "live": live_ready_json(),
+            "stream": {
+                "state": "live",
+                "changed_at": 2,
+                "started_at": 1,
+                "ended_at": null,
+                "end_reason": null,
+            },
+        }));
+    }
+
+    #[test]
+    fn presence_frames() {
+        let event = GatewayEvent::PresenceSnapshot(PresenceSnapshot { members: vec![user()] });
+        assert_frame(event, 13, json!({"members": [user_json()]}));
+
+        assert_frame(GatewayEvent::PresenceJoin(user()), 14, user_json());
+        assert_frame(GatewayEvent::PresenceLeave(user()), 15, user_json());
+    }
+
+    #[test]
+    fn bandwidth_warning_frame() {
+        let event = GatewayEvent::BandwidthWarning(BandwidthWarning {
+            scope: LimitScope::Room,
+            byte_rate: 200,
+            limit: 100,
+            action: Some(LimitAction::Throttle),
+        });
+        assert_frame(event, 23, json!({
+            "scope": "room",
+            "byte_rate": 200,
+            "limit": 100,
+            "action": "throttle",
+        }));
+    }
+
+    #[test]
+    fn backend_events_are_decoded() {
+        let event: GatewayEvent = serde_json::from_value(json!({
+            "opcode": 0,
+            "payload": {"members": 3, "multiplier": "1.2x"},
+        })).unwrap();
+        assert!(matches!(event, GatewayEvent::StatsUpdate(_)));
+
+        let event: GatewayEvent = serde_json::from_value(json!({
+            "opcode": 2,
+            "payload": {"stream_url": "http://live/live/a.m3u8"},
+        })).unwrap();
+        assert!(matches!(event, GatewayEvent::LiveReady(ready) if ready.live_at == 0));
+
+        let event: GatewayEvent = serde_json::from_value(json!({"opcode": 5})).unwrap();
+        assert!(matches!(event, GatewayEvent::Message(Value::Null)));
+    }
+
+    #[test]
+    fn gateway_events_are_not_decoded() {
+        for opcode in [3, 6, 10, 12, 16, 19, 22, 23] {
+            let result = serde_json::from_value::<GatewayEvent>(json!({"opcode": opcode}));
+            assert!(result.is_err(), "opcode {} should be refused", opcode);
+        }
+    }
+
+    #[test]
+    fn client_frames_are_decoded() {
+        let event: ClientEvent = serde_json::from_value(json!({"opcode": 1})).unwrap();
+        assert!(matches!(event, ClientEvent::Heartbeat));
+
+        let event: ClientEvent = serde_json::from_value(json!({
+            "opcode": 7,
+            "payload": {"type": "reaction", "emoji": "🎉"},
+        })).unwrap();
+        assert!(matches!(event, ClientEvent::SendMessage(MessageContent::Reaction { .. })));
+
+        let event: ClientEvent = serde_json::from_value(json!({
+            "opcode": 11,
+            "payload": {"token": "t"},
+        })).unwrap();
+        assert!(matches!(event, ClientEvent::Identify(identify) if identify.token == "t"));
+
+        let event: ClientEvent = serde_json::from_value(json!({
+            "opcode": 17,
+            "payload": {"token": "t", "session_id": "s", "seq": 3},
+        })).unwrap();
+        assert!(matches!(event, ClientEvent::Resume(resume) if resume.seq == 3));
+
+        assert!(serde_json::from_value::<ClientEvent>(json!({"opcode": 0})).is_err());
+    }
+}
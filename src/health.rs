@@ -0,0 +1,153 @@
+use futures::future;
+use serde::Serialize;
+use tokio::time::{Duration, Instant};
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use crate::config;
+use crate::managers::RoomManager;
+
+
+lazy_static! {
+    static ref CLIENT: reqwest::Client = reqwest::Client::new();
+
+    /// The last time each live server was probed and what was found.
+    static ref PROBES: Mutex<HashMap<String, Probe>> = Mutex::new(HashMap::new());
+}
+
+
+/// A cached reachability check of a live server.
+#[derive(Clone)]
+struct Probe {
+    checked_at: Instant,
+    result: Result<(), String>,
+}
+
+
+/// The result of one readiness check.
+#[derive(Debug, Serialize)]
+pub struct Check {
+    /// What was checked.
+    pub name: &'static str,
+
+    /// If the check passed.
+    pub ok: bool,
+
+    /// Why the check failed.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub message: Option<String>,
+}
+
+impl Check {
+    fn new(name: &'static str, result: Result<(), String>) -> Self {
+        Self {
+            name,
+            ok: result.is_ok(),
+            message: result.err(),
+        }
+    }
+}
+
+
+/// If the gateway should be sent traffic and the checks that decided it.
+#[derive(Debug, Serialize)]
+pub struct Readiness {
+    pub status: u16,
+    pub message: &'static str,
+    pub ready: bool,
+    pub checks: Vec<Check>,
+}
+
+impl Readiness {
+    /// Removes why each check failed, which can name internal hosts and
+    /// paths, leaving only if it passed.
+    pub fn redact(&mut self) {
+        for check in self.checks.iter_mut() {
+            check.message = None;
+        }
+    }
+}
+
+
+/// Runs every readiness check.
+///
+/// The gateway is not ready while it is draining, if a room's state was
+/// poisoned, if a live server a room uses can not be reached or if the
+/// room store can not be saved to.
+pub async fn readiness(rooms: &RoomManager) -> Readiness {
+    let draining = if rooms.is_draining() {
+        Err("the gateway is shutting down".to_string())
+    } else {
+        Ok(())
+    };
+
+    let checks = vec![
+        Check::new("draining", draining),
+        Check::new("rooms", rooms.check_rooms()),
+        Check::new("live_servers", check_live_servers(rooms.live_servers()).await),
+        Check::new("persistence", rooms.check_store().await),
+    ];
+
+    let ready = checks.iter().all(|check| check.ok);
+    let (status, message) = if ready {
+        (200, "Ready!")
+    } else {
+        (503, "Not ready!")
+    };
+
+    Readiness {
+        status,
+        message,
+        ready,
+        checks,
+    }
+}
+
+
+/// Checks every live server can be reached, probing each at most once
+/// every `LIVE_SERVER_CHECK_TTL` seconds.
+async fn check_live_servers(servers: Vec<String>) -> Result<(), String> {
+    let results = future::join_all(servers.iter().map(|server| probe(server))).await;
+
+    let failed: Vec<String> = servers
+        .iter()
+        .zip(results)
+        .filter_map(|(server, result)| {
+            result.err().map(|e| format!("{}: {}", server, e))
+        })
+        .collect();
+
+    if failed.is_empty() {
+        Ok(())
+    } else {
+        Err(failed.join(", "))
+    }
+}
+
+
+/// Checks a live server answers http requests, any response counts as
+/// reachable.
+async fn probe(server: &str) -> Result<(), String> {
+    let ttl = Duration::from_secs(config::get().live_server_check_ttl);
+    if let Some(probe) = PROBES.lock().unwrap().get(server) {
+        if probe.checked_at.elapsed() < ttl {
+            return probe.result.clone()
+        }
+    }
+
+    let result = CLIENT
+        .get(server)
+        .timeout(Duration::from_secs(config::get().live_server_check_timeout))
+        .send()
+        .await
+        .map(|_| ())
+        .map_err(|e| e.to_string());
+
+    PROBES.lock().unwrap().insert(server.to_string(), Probe {
+        checked_at: Instant::now(),
+        result: result.clone(),
+    });
+
+    result
+}
@@ -0,0 +1,141 @@
+use jsonwebtoken::{decode, Algorithm, DecodingKey, Validation};
+use serde::{Serialize, Deserialize};
+
+use std::fmt;
+use std::time::Duration;
+
+use crate::config;
+
+
+lazy_static! {
+    static ref CLIENT: reqwest::Client = reqwest::Client::new();
+}
+
+
+/// The user a connection has identified as.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UserProfile {
+    /// The user's id in the main app.
+    pub id: String,
+
+    /// The name shown to other members.
+    pub display_name: String,
+
+    /// The url of the user's avatar if they have one.
+    #[serde(default)]
+    pub avatar: Option<String>,
+}
+
+
+/// The claims the gateway reads from a locally signed session token.
+#[derive(Deserialize)]
+struct SessionClaims {
+    /// The user's id.
+    sub: String,
+
+    /// The user's display name.
+    name: String,
+
+    /// The url of the user's avatar.
+    #[serde(default)]
+    avatar: Option<String>,
+}
+
+impl From<SessionClaims> for UserProfile {
+    fn from(claims: SessionClaims) -> Self {
+        Self {
+            id: claims.sub,
+            display_name: claims.name,
+            avatar: claims.avatar,
+        }
+    }
+}
+
+
+/// The reasons a session token can be refused.
+#[derive(Debug)]
+pub enum IdentifyError {
+    /// Neither a shared secret or an auth endpoint is configured.
+    NotConfigured,
+
+    /// The token is expired, badly signed or malformed.
+    InvalidToken(String),
+
+    /// The auth endpoint could not be reached or refused the token.
+    Endpoint(String),
+}
+
+impl fmt::Display for IdentifyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::NotConfigured => write!(f, "no session validation is configured"),
+            Self::InvalidToken(e) => write!(f, "invalid session token: {}", e),
+            Self::Endpoint(e) => write!(f, "auth endpoint refused session: {}", e),
+        }
+    }
+}
+
+
+/// Logs how session tokens are going to be validated, warning if every
+/// client is going to be refused.
+pub fn log_validation_mode() {
+    if let Some(endpoint) = config::get().auth_endpoint.as_ref() {
+        tracing::info!(%endpoint, "Validating sessions with the auth endpoint");
+    } else if config::get().auth_secret.is_some() {
+        tracing::info!("Validating sessions with the shared secret");
+    } else {
+        tracing::warn!("No AUTH_SECRET or AUTH_ENDPOINT set, all clients will fail to identify");
+    }
+}
+
+
+/// Validates a session token, returning the user it belongs to.
+///
+/// If `AUTH_ENDPOINT` is set the token is sent there to be checked,
+/// otherwise it is checked locally as a HS256 JWT signed with `AUTH_SECRET`.
+pub async fn validate_token(token: &str) -> Result<UserProfile, IdentifyError> {
+    if let Some(endpoint) = config::get().auth_endpoint.as_ref() {
+        validate_remote(endpoint, token).await
+    } else if let Some(secret) = config::get().auth_secret.as_ref() {
+        validate_local(secret, token)
+    } else {
+        Err(IdentifyError::NotConfigured)
+    }
+}
+
+
+/// Checks the token's signature and expiry with the shared secret.
+fn validate_local(secret: &str, token: &str) -> Result<UserProfile, IdentifyError> {
+    let data = decode::<SessionClaims>(
+        token,
+        &DecodingKey::from_secret(secret.as_bytes()),
+        &Validation::new(Algorithm::HS256),
+    ).map_err(|e| IdentifyError::InvalidToken(e.to_string()))?;
+
+    Ok(data.claims.into())
+}
+
+
+/// Asks the auth endpoint who the token belongs to.
+///
+/// The endpoint is sent `{"token": "..."}` and is expected to respond
+/// with a 200 and the user's profile for a valid session within
+/// `AUTH_ENDPOINT_TIMEOUT` seconds.
+async fn validate_remote(endpoint: &str, token: &str) -> Result<UserProfile, IdentifyError> {
+    let resp = CLIENT
+        .post(endpoint)
+        .json(&serde_json::json!({ "token": token }))
+        .timeout(Duration::from_secs(config::get().auth_endpoint_timeout))
+        .send()
+        .await
+        .map_err(|e| IdentifyError::Endpoint(e.to_string()))?;
+
+    let status = resp.status();
+    if !status.is_success() {
+        return Err(IdentifyError::Endpoint(format!("status {}", status.as_str())))
+    }
+
+    resp.json::<UserProfile>()
+        .await
+        .map_err(|e| IdentifyError::Endpoint(e.to_string()))
+}
@@ -0,0 +1,332 @@
+use serde::{Serialize, Deserialize};
+
+use std::collections::{HashMap, HashSet, VecDeque};
+
+use crate::config;
+use crate::utils;
+
+
+/// The XP a user has earned in a room.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct UserXp {
+    /// How long the user has watched the room's stream for in ms.
+    watch_time: u64,
+
+    /// The XP the user has earned in total.
+    earned: f64,
+
+    /// The XP that has been paid out to the user.
+    settled: f64,
+}
+
+
+/// A user's XP in a room as shown by the api.
+#[derive(Debug, Clone, Serialize)]
+pub struct LedgerEntry {
+    pub user_id: String,
+
+    /// How long the user has watched the room's stream for in ms.
+    pub watch_time: u64,
+
+    /// The XP the user has earned in total.
+    pub earned: f64,
+
+    /// The XP that has been paid out to the user.
+    pub settled: f64,
+
+    /// The XP waiting to be paid out.
+    pub pending: f64,
+}
+
+
+/// The XP each user has earned in a room.
+///
+/// Viewers earn `XP_PER_MINUTE` for every minute they watch while the
+/// stream is live, scaled by the room's multiplier at the time. Earned XP
+/// is brought up to date whenever a viewer joins or leaves, the
+/// multiplier changes or the stream goes live or stops, so every stretch
+/// of time is credited at the multiplier it was watched at.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Ledger {
+    users: HashMap<String, UserXp>,
+
+    /// The users currently watching.
+    #[serde(skip)]
+    watching: HashSet<String>,
+
+    /// If the stream can currently be watched.
+    #[serde(skip)]
+    live: bool,
+
+    /// The room's current multiplier.
+    #[serde(skip)]
+    multiplier: f64,
+
+    /// When XP was last credited as a unix timestamp in ms.
+    #[serde(skip)]
+    accrued_at: u64,
+}
+
+impl Ledger {
+    /// Starts crediting a user.
+    pub fn join(&mut self, user_id: &str) {
+        self.accrue();
+        self.watching.insert(user_id.to_string());
+        self.users
+            .entry(user_id.to_string())
+            .or_insert(UserXp {
+                watch_time: 0,
+                earned: 0.0,
+                settled: 0.0,
+            });
+    }
+
+    /// Stops crediting a user.
+    pub fn leave(&mut self, user_id: &str) {
+        self.accrue();
+        self.watching.remove(user_id);
+    }
+
+    /// Records the stream going live or stopping.
+    pub fn set_live(&mut self, live: bool) {
+        self.accrue();
+        self.live = live;
+    }
+
+    /// Records the room's multiplier changing.
+    pub fn set_multiplier(&mut self, multiplier: f64) {
+        self.accrue();
+        self.multiplier = multiplier;
+    }
+
+    /// Credits everyone watching for the time since XP was last credited.
+    pub fn accrue(&mut self) {
+        let now = utils::now_millis();
+        let elapsed = now.saturating_sub(self.accrued_at);
+        self.accrued_at = now;
+
+        if !self.live {
+            return
+        }
+
+        let xp = elapsed as f64 / 60_000.0 * config::get().xp_per_minute * self.multiplier;
+        for user_id in self.watching.iter() {
+            if let Some(user) = self.users.get_mut(user_id) {
+                user.watch_time += elapsed;
+                user.earned += xp;
+            }
+        }
+    }
+
+    /// Every user's XP, brought up to date.
+    pub fn entries(&mut self) -> Vec<LedgerEntry> {
+        self.accrue();
+
+        let mut entries: Vec<LedgerEntry> = self.users
+            .iter()
+            .map(|(user_id, user)| entry(user_id, user))
+            .collect();
+
+        entries.sort_by(|a, b| a.user_id.cmp(&b.user_id));
+        entries
+    }
+
+    /// A user's XP brought up to date, None if they have never watched.
+    pub fn entry(&mut self, user_id: &str) -> Option<LedgerEntry> {
+        self.accrue();
+        self.users.get(user_id).map(|user| entry(user_id, user))
+    }
+
+    /// Marks every user's pending XP as paid out, returning how much each
+    /// user was paid.
+    pub fn settle(&mut self) -> Vec<(String, f64)> {
+        self.accrue();
+
+        let mut settled = Vec::new();
+        for (user_id, user) in self.users.iter_mut() {
+            let pending = user.earned - user.settled;
+            if pending > 0.0 {
+                user.settled = user.earned;
+                settled.push((user_id.clone(), pending));
+            }
+        }
+
+        settled
+    }
+
+    /// If any user has XP waiting to be paid out.
+    pub fn has_pending(&self) -> bool {
+        self.users.values().any(|user| user.earned > user.settled)
+    }
+}
+
+
+fn entry(user_id: &str, user: &UserXp) -> LedgerEntry {
+    LedgerEntry {
+        user_id: user_id.to_string(),
+        watch_time: user.watch_time,
+        earned: user.earned,
+        settled: user.settled,
+        pending: user.earned - user.settled,
+    }
+}
+
+
+/// XP paid out to a user for watching a room.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Payout {
+    pub room_id: String,
+    pub user_id: String,
+    pub xp: f64,
+}
+
+
+/// Every payout made by one request to the payouts route.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PayoutBatch {
+    /// The id the batch was requested with.
+    pub payout_id: String,
+
+    /// When the batch was made as a unix timestamp in ms.
+    pub created_at: u64,
+
+    /// The XP paid out to each user in each room.
+    pub payouts: Vec<Payout>,
+}
+
+
+/// The most recent payout batches, kept so a request that is retried
+/// with the same id gets the same batch back rather than settling again.
+pub struct PayoutLog {
+    batches: VecDeque<PayoutBatch>,
+}
+
+impl PayoutLog {
+    pub fn new() -> Self {
+        Self {
+            batches: VecDeque::new(),
+        }
+    }
+
+    /// Rebuilds the log from the batches it kept, oldest first.
+    pub fn restore(batches: Vec<PayoutBatch>) -> Self {
+        let mut log = Self::new();
+        for batch in batches {
+            log.push(batch);
+        }
+        log
+    }
+
+    /// The kept batches, oldest first.
+    pub fn batches(&self) -> Vec<PayoutBatch> {
+        self.batches.iter().cloned().collect()
+    }
+
+    /// The batch made with the given id, if it is still kept.
+    pub fn get(&self, payout_id: &str) -> Option<&PayoutBatch> {
+        self.batches
+            .iter()
+            .find(|batch| batch.payout_id == payout_id)
+    }
+
+    /// Keeps a batch, forgetting the oldest once there are more than
+    /// `PAYOUT_HISTORY`.
+    pub fn push(&mut self, batch: PayoutBatch) {
+        self.batches.push_back(batch);
+        while self.batches.len() > config::get().payout_history {
+            self.batches.pop_front();
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Pretends the last time XP was credited was `ms` earlier.
+    fn rewind(ledger: &mut Ledger, ms: u64) {
+        ledger.accrued_at -= ms;
+    }
+
+    fn earned(ledger: &mut Ledger, user_id: &str) -> f64 {
+        ledger.entry(user_id).map(|entry| entry.earned).unwrap_or(0.0)
+    }
+
+    fn live_ledger(multiplier: f64) -> Ledger {
+        config::init_default();
+
+        let mut ledger = Ledger::default();
+        ledger.set_live(true);
+        ledger.set_multiplier(multiplier);
+        ledger
+    }
+
+    #[test]
+    fn watching_a_live_stream_earns_xp() {
+        let mut ledger = live_ledger(2.0);
+        ledger.join("a");
+        rewind(&mut ledger, 60_000);
+
+        let entry = ledger.entry("a").unwrap();
+        assert!((entry.earned - 2.0).abs() < 0.01);
+        assert!(entry.watch_time >= 60_000);
+        assert!((entry.pending - entry.earned).abs() < f64::EPSILON);
+    }
+
+    #[test]
+    fn nothing_is_earned_while_not_live() {
+        let mut ledger = live_ledger(1.0);
+        ledger.set_live(false);
+        ledger.join("a");
+        rewind(&mut ledger, 60_000);
+
+        assert_eq!(earned(&mut ledger, "a"), 0.0);
+    }
+
+    #[test]
+    fn nothing_is_earned_after_leaving() {
+        let mut ledger = live_ledger(1.0);
+        ledger.join("a");
+        rewind(&mut ledger, 60_000);
+        ledger.leave("a");
+        let before = earned(&mut ledger, "a");
+
+        rewind(&mut ledger, 60_000);
+        assert!((earned(&mut ledger, "a") - before).abs() < f64::EPSILON);
+    }
+
+    #[test]
+    fn time_is_credited_at_the_multiplier_it_was_watched_at() {
+        let mut ledger = live_ledger(1.0);
+        ledger.join("a");
+        rewind(&mut ledger, 60_000);
+        ledger.set_multiplier(3.0);
+        rewind(&mut ledger, 60_000);
+
+        assert!((earned(&mut ledger, "a") - 4.0).abs() < 0.01);
+    }
+
+    #[test]
+    fn settling_pays_out_pending_xp_once() {
+        let mut ledger = live_ledger(1.0);
+        ledger.join("a");
+        ledger.join("b");
+        rewind(&mut ledger, 60_000);
+        ledger.set_live(false);
+        assert!(ledger.has_pending());
+
+        let mut settled = ledger.settle();
+        settled.sort_by(|a, b| a.0.cmp(&b.0));
+        assert_eq!(settled.len(), 2);
+        assert_eq!(settled[0].0, "a");
+        assert!((settled[0].1 - 1.0).abs() < 0.01);
+
+        assert!(!ledger.has_pending());
+        assert!(ledger.settle().is_empty());
+
+        let entry = ledger.entry("a").unwrap();
+        assert_eq!(entry.pending, 0.0);
+        assert_eq!(entry.settled, entry.earned);
+    }
+}
@@ -0,0 +1,185 @@
+use serde::{Serialize, Deserialize};
+
+use crate::utils;
+
+
+/// Where a room's stream is in its lifecycle.
+///
+/// A room starts `Idle`, a stream goes `Starting` when it is published,
+/// `Live` once data is flowing, `Stalled` if the data stops flowing while
+/// it is still published and `Ended` when it is unpublished. An ended
+/// room goes back to `Starting` when the next stream is published.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StreamState {
+    /// Nothing has been streamed to the room yet.
+    Idle,
+
+    /// The stream has been published but no data is flowing yet.
+    Starting,
+
+    /// The stream is published and data is flowing.
+    Live,
+
+    /// The stream is still published but data has stopped flowing.
+    Stalled,
+
+    /// The stream has been unpublished.
+    Ended,
+}
+
+impl StreamState {
+    /// If the stream can currently be watched.
+    pub fn is_live(self) -> bool {
+        matches!(self, Self::Live | Self::Stalled)
+    }
+
+    /// If a stream is currently published.
+    fn is_published(self) -> bool {
+        matches!(self, Self::Starting | Self::Live | Self::Stalled)
+    }
+}
+
+
+/// The state of a room's stream and when it got there.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Lifecycle {
+    /// The current state of the stream.
+    pub state: StreamState,
+
+    /// When the stream entered its current state as a unix timestamp in ms.
+    pub changed_at: u64,
+
+    /// When the current or most recent stream was published as a unix
+    /// timestamp in ms.
+    pub started_at: Option<u64>,
+
+    /// When the most recent stream ended as a unix timestamp in ms.
+    pub ended_at: Option<u64>,
+
+    /// Why the most recent stream ended.
+    pub end_reason: Option<String>,
+}
+
+impl Lifecycle {
+    /// The lifecycle of a room that has never been streamed to.
+    pub fn new() -> Self {
+        Self {
+            state: StreamState::Idle,
+            changed_at: utils::now_millis(),
+            started_at: None,
+            ended_at: None,
+            end_reason: None,
+        }
+    }
+
+    /// Records a stream being published, returning true if the state
+    /// changed.
+    pub fn published(&mut self) -> bool {
+        if self.state.is_published() {
+            return false
+        }
+
+        self.started_at = Some(utils::now_millis());
+        self.ended_at = None;
+        self.end_reason = None;
+        self.set(StreamState::Starting)
+    }
+
+    /// Records a sample of the published stream, returning true if the
+    /// state changed.
+    pub fn sampled(&mut self, flowing: bool) -> bool {
+        match (self.state, flowing) {
+            (StreamState::Starting, true) => self.set(StreamState::Live),
+            (StreamState::Stalled, true) => self.set(StreamState::Live),
+            (StreamState::Live, false) => self.set(StreamState::Stalled),
+            _ => false,
+        }
+    }
+
+    /// Records the stream ending, returning true if the state changed.
+    pub fn ended(&mut self, reason: &'static str) -> bool {
+        if !self.state.is_published() {
+            return false
+        }
+
+        self.ended_at = Some(utils::now_millis());
+        self.end_reason = Some(reason.to_string());
+        self.set(StreamState::Ended)
+    }
+
+    fn set(&mut self, state: StreamState) -> bool {
+        self.state = state;
+        self.changed_at = utils::now_millis();
+        true
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stream_goes_through_its_lifecycle() {
+        let mut lifecycle = Lifecycle::new();
+        assert_eq!(lifecycle.state, StreamState::Idle);
+
+        assert!(lifecycle.published());
+        assert_eq!(lifecycle.state, StreamState::Starting);
+        assert!(lifecycle.started_at.is_some());
+
+        assert!(lifecycle.sampled(true));
+        assert_eq!(lifecycle.state, StreamState::Live);
+
+        assert!(lifecycle.sampled(false));
+        assert_eq!(lifecycle.state, StreamState::Stalled);
+
+        assert!(lifecycle.sampled(true));
+        assert_eq!(lifecycle.state, StreamState::Live);
+
+        assert!(lifecycle.ended("unpublished"));
+        assert_eq!(lifecycle.state, StreamState::Ended);
+        assert!(lifecycle.ended_at.is_some());
+        assert_eq!(lifecycle.end_reason.as_deref(), Some("unpublished"));
+    }
+
+    #[test]
+    fn unchanged_states_are_not_reported() {
+        let mut lifecycle = Lifecycle::new();
+        assert!(!lifecycle.sampled(true));
+        assert!(!lifecycle.ended("offline"));
+
+        lifecycle.published();
+        assert!(!lifecycle.published());
+        assert!(!lifecycle.sampled(false));
+
+        lifecycle.sampled(true);
+        assert!(!lifecycle.sampled(true));
+
+        lifecycle.ended("offline");
+        assert!(!lifecycle.ended("offline"));
+        assert!(!lifecycle.sampled(true));
+    }
+
+    #[test]
+    fn next_stream_clears_the_last_end() {
+        let mut lifecycle = Lifecycle::new();
+        lifecycle.published();
+        lifecycle.ended("unpublished");
+
+        assert!(lifecycle.published());
+        assert_eq!(lifecycle.state, StreamState::Starting);
+        assert!(lifecycle.ended_at.is_none());
+        assert!(lifecycle.end_reason.is_none());
+    }
+
+    #[test]
+    fn only_flowing_streams_are_live() {
+        assert!(!StreamState::Idle.is_live());
+        assert!(!StreamState::Starting.is_live());
+        assert!(StreamState::Live.is_live());
+        assert!(StreamState::Stalled.is_live());
+        assert!(!StreamState::Ended.is_live());
+    }
+}
@@ -0,0 +1,159 @@
+use serde::{Serialize, Deserialize};
+use tracing_subscriber::filter::{EnvFilter, ParseError};
+use tracing_subscriber::fmt::writer::MakeWriterExt;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::{fmt, reload, Registry};
+
+use std::fmt as std_fmt;
+use std::io::{self, IsTerminal};
+use std::sync::{Mutex, OnceLock};
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::Ordering::Relaxed;
+
+use crate::config;
+
+static HANDLES: OnceLock<Handles> = OnceLock::new();
+
+/// If log lines are written as JSON rather than pretty.
+static JSON: AtomicBool = AtomicBool::new(false);
+
+
+/// How log lines are written to stdout.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LogFormat {
+    /// Human readable lines with the fields of every span they are in.
+    Pretty,
+
+    /// One JSON object per line for log pipelines.
+    Json,
+}
+
+
+/// The gateway's current logging settings.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LogSettings {
+    /// How log lines are written.
+    pub format: LogFormat,
+
+    /// The `EnvFilter` directives deciding which levels are logged for
+    /// each module e.g. `info,gateway::ws=debug`.
+    pub filter: String,
+}
+
+
+/// A change to the logging settings, anything not given is kept.
+#[derive(Debug, Deserialize)]
+pub struct LogUpdate {
+    pub format: Option<LogFormat>,
+    pub filter: Option<String>,
+}
+
+
+/// The reasons the logging settings can fail to change.
+#[derive(Debug)]
+pub enum LogError {
+    /// The filter directives could not be parsed.
+    Filter(ParseError),
+
+    /// The subscriber has gone away.
+    Reload(reload::Error),
+}
+
+impl std_fmt::Display for LogError {
+    fn fmt(&self, f: &mut std_fmt::Formatter<'_>) -> std_fmt::Result {
+        match self {
+            Self::Filter(e) => write!(f, "invalid log filter: {}", e),
+            Self::Reload(e) => write!(f, "failed to reload logging: {}", e),
+        }
+    }
+}
+
+
+/// The handle the subscriber's filter is swapped through.
+struct Handles {
+    filter: reload::Handle<EnvFilter, Registry>,
+    settings: Mutex<LogSettings>,
+}
+
+
+/// Installs the global subscriber with `LOG_FORMAT` and `LOG_FILTER`.
+///
+/// Both can be changed later with `update` without restarting. Both
+/// formats' layers are always installed and only the selected one writes,
+/// as a layer can only format the spans that were opened while it was
+/// installed.
+pub fn init() -> Result<(), LogError> {
+    let config = config::get();
+    let filter = EnvFilter::try_new(&config.log_filter).map_err(LogError::Filter)?;
+    let (filter_layer, filter) = reload::Layer::new(filter);
+    JSON.store(config.log_format == LogFormat::Json, Relaxed);
+
+    let pretty = fmt::layer()
+        .with_ansi(io::stdout().is_terminal())
+        .with_writer(io::stdout.with_filter(|_| !JSON.load(Relaxed)));
+    let json = fmt::layer()
+        .json()
+        .with_current_span(true)
+        .with_span_list(true)
+        .with_writer(io::stdout.with_filter(|_| JSON.load(Relaxed)));
+
+    tracing_subscriber::registry()
+        .with(filter_layer)
+        .with(pretty)
+        .with(json)
+        .init();
+
+    let _ = HANDLES.set(Handles {
+        filter,
+        settings: Mutex::new(LogSettings {
+            format: config.log_format,
+            filter: config.log_filter.clone(),
+        }),
+    });
+
+    Ok(())
+}
+
+
+/// The current logging settings.
+pub fn settings() -> Option<LogSettings> {
+    HANDLES.get().map(|handles| handles.settings.lock().unwrap().clone())
+}
+
+
+/// Changes the log format and filter while the gateway is running.
+///
+/// The filter is checked before anything is changed so a bad update
+/// leaves the settings as they were.
+pub fn update(update: LogUpdate) -> Result<LogSettings, LogError> {
+    let handles = HANDLES.get().expect("logging has been initialised");
+    let mut settings = handles.settings.lock().unwrap();
+
+    let filter = update.filter
+        .map(|directives| {
+            EnvFilter::try_new(&directives)
+                .map(|filter| (directives, filter))
+                .map_err(LogError::Filter)
+        })
+        .transpose()?;
+
+    if let Some((directives, filter)) = filter {
+        handles.filter.reload(filter).map_err(LogError::Reload)?;
+        settings.filter = directives;
+    }
+
+    if let Some(format) = update.format {
+        JSON.store(format == LogFormat::Json, Relaxed);
+        settings.format = format;
+    }
+
+    Ok(settings.clone())
+}
+
+
+/// Checks filter directives can be parsed.
+pub fn check_filter(directives: &str) -> bool {
+    EnvFilter::try_new(directives).is_ok()
+}
@@ -2,20 +2,44 @@
 extern crate lazy_static;
 
 mod ws;
+mod auth;
 mod managers;
 mod opcodes;
+mod events;
 mod utils;
+mod config;
+mod logging;
+mod identity;
+mod ledger;
+mod lifecycle;
+mod presence;
+mod resume;
+mod watcher;
+mod stats;
+mod store;
+mod shutdown;
+mod metrics;
+mod multiplier;
+mod health;
+mod bandwidth;
+mod series;
+mod analytics;
 
-use managers::RoomManager;
+use managers::{RoomManager, RoomFilter};
+use multiplier::MultiplierKind;
+use series::HistoryQuery;
+use events::GatewayEvent;
+use stats::{StatsBackend, StatsSample, StreamStatus};
 use ws::connect_client;
 
-use warp::Filter;
+use warp::{Filter, Reply};
 use warp::reply;
 use warp::ws::Ws;
 use warp::reply::Response;
 use warp::http::header::ACCESS_CONTROL_ALLOW_ORIGIN;
 use warp::http::header::ACCESS_CONTROL_ALLOW_METHODS;
 use warp::http::header::ACCESS_CONTROL_ALLOW_HEADERS;
+use warp::http::header::{LINK, LOCATION};
 use warp::hyper::header::HeaderValue;
 use warp::http::StatusCode;
 
@@ -24,15 +48,114 @@ use serde_json::json;
 use serde::Deserialize;
 
 
+/// The payout requested with `POST /v1/payouts`.
+#[derive(Debug, Deserialize)]
+pub struct PayoutRequest {
+    /// The id the payout is made under, retrying with the same id gets
+    /// the same payout back.
+    pub payout_id: String,
+}
+
+
+/// The room to make with `POST /v1/rooms`.
+#[derive(Debug, Deserialize)]
+pub struct CreateRoom {
+    pub room_id: String,
+    pub live_server: String,
+
+    /// Where the room's stream stats come from, polling the live
+    /// server's JSON api if not given.
+    #[serde(default)]
+    pub stats_backend: StatsBackend,
+
+    /// How the room's XP multiplier is worked out, the log10 of the
+    /// members if not given.
+    #[serde(default)]
+    pub multiplier_policy: MultiplierKind,
+
+    /// The most bytes per second the room's stream may average, 0 for no
+    /// limit, `ROOM_BYTE_RATE_LIMIT` if not given.
+    #[serde(default)]
+    pub byte_rate_limit: Option<usize>,
+}
+
+
 #[derive(Debug, Deserialize)]
 pub struct ListOptions {
     pub live_server: String,
+
+    /// Where the room's stream stats come from, polling the live
+    /// server's JSON api if not given.
+    #[serde(default)]
+    pub stats_backend: StatsBackend,
+}
+
+
+/// Stats pushed to a room by the live server.
+#[derive(Debug, Deserialize)]
+pub struct PushedStats {
+    /// If the stream is currently live.
+    pub live: bool,
+
+    /// The total amount of bytes streamed.
+    #[serde(default)]
+    pub total_bytes: usize,
+
+    /// The current rate of transfer in bytes per second.
+    #[serde(default)]
+    pub bytes_per_sec: usize,
+}
+
+
+/// A stream event sent by the live server's webhooks.
+///
+/// This is sent form encoded as nginx-rtmp does, the stream's name is the
+/// id of the room it belongs to and any other fields are ignored.
+#[derive(Debug, Deserialize)]
+pub struct StreamHook {
+    /// The name of the stream.
+    pub name: String,
+
+    /// The total amount of bytes streamed, only sent to `on_stats`.
+    #[serde(default)]
+    pub total_bytes: usize,
+
+    /// The current rate of transfer in bytes per second, only sent to
+    /// `on_stats`.
+    #[serde(default)]
+    pub bytes_per_sec: usize,
+}
+
+
+#[derive(Debug, Deserialize)]
+pub struct GatewayOptions {
+    /// The protocol version the client wants to speak.
+    pub v: Option<u8>,
 }
 
 
 #[tokio::main]
 async fn main() {
-    let room_manager1 = RoomManager::new();
+    if let Err(e) = config::init() {
+        eprintln!("[ SERVER ERROR ] Invalid config: {}", e);
+        std::process::exit(1);
+    }
+
+    if let Err(e) = logging::init() {
+        eprintln!("[ SERVER ERROR ] Failed to start logging: {}", e);
+        std::process::exit(1);
+    }
+
+    let store = config::get().store_backend.build(&config::get().store_path);
+    let room_manager1 = RoomManager::new(store);
+    match room_manager1.load_rooms().await {
+        Ok(count) => tracing::info!(count, "Restored rooms"),
+        Err(error) => tracing::error!(%error, "Failed to restore rooms"),
+    }
+    tokio::spawn(room_manager1.clone().supervise_watchers());
+    tokio::spawn(room_manager1.clone().persist_rooms());
+    let shutdown_rooms = room_manager1.clone();
+    let signal_rooms = room_manager1.clone();
     let room_manager = move || {
         let inst = room_manager1.clone();
         warp::any().map(move || inst.clone())
@@ -42,57 +165,207 @@ async fn main() {
     let gateway = warp::path!("ws" / String)
         // The `ws()` filter will prepare Websocket handshake...
         .and(warp::ws())
+        .and(warp::query::<GatewayOptions>())
+        .and(room_manager())
+        .map(|room_id: String, ws: Ws, options: GatewayOptions, rooms: RoomManager| {
+            if rooms.is_draining() {
+                ws::upgrade_failed("draining");
+                return message_reply(StatusCode::SERVICE_UNAVAILABLE, "The gateway is shutting down!")
+                    .into_response()
+            }
+
+            let version = options.v.unwrap_or(events::PROTOCOL_VERSION);
+            ws.max_message_size(config::get().max_frame_size).on_upgrade(move |socket| {
+                connect_client(socket, room_id, version, rooms)
+            }).into_response()
+        });
+
+    // POST v1/rooms/ -> Makes a room
+    let create_room = warp::path!("v1" / "rooms")
+        .and(warp::post())
+        .and(room_manager())
+        .and(auth::signed_json())
+        .map(|rooms: RoomManager, options: CreateRoom| {
+            if options.room_id.is_empty() {
+                return message_reply(StatusCode::BAD_REQUEST, "The room id can not be empty!")
+                    .into_response()
+            }
+
+            if let Err(message) = options.multiplier_policy.validate() {
+                return message_reply(StatusCode::BAD_REQUEST, message).into_response()
+            }
+
+            let created = rooms.create_room(
+                options.room_id.clone(),
+                options.live_server,
+                options.stats_backend,
+                options.multiplier_policy,
+                options.byte_rate_limit,
+            );
+            if !created {
+                return message_reply(StatusCode::CONFLICT, "This room already exists!")
+                    .into_response()
+            }
+
+            let summary = rooms.get_summary(&options.room_id);
+            let rep = reply::with_status(reply::json(&summary), StatusCode::CREATED);
+            reply::with_header(rep, LOCATION, format!("/v1/rooms/{}", options.room_id))
+                .into_response()
+        });
+
+    // DELETE v1/rooms/<room_id>/ -> Removes a room
+    let delete_room = warp::path!("v1" / "rooms" / String)
+        .and(warp::delete())
+        .and(auth::require_api_key())
+        .and(room_manager())
+        .then(|room_id: String, rooms: RoomManager| async move {
+            if rooms.delete_room(room_id).await {
+                StatusCode::NO_CONTENT.into_response()
+            } else {
+                message_reply(StatusCode::NOT_FOUND, "This room does not exist!").into_response()
+            }
+        });
+
+    // POST v1/rooms/<room_id>/events/ -> Emits a gateway event to a room
+    let room_events = warp::path!("v1" / "rooms" / String / "events")
+        .and(warp::post())
+        .and(room_manager())
+        .and(auth::signed_body())
+        .map(|room_id: String, rooms: RoomManager, body: Bytes| {
+            let resp = match emit_event(&rooms, &room_id, &body) {
+                Ok(()) => StatusCode::NO_CONTENT.into_response(),
+                Err((status, message)) => message_reply(status, message).into_response(),
+            };
+
+            with_cors(resp)
+        });
+
+    // GET v1/rooms/<room_id>/xp/ -> The XP every user has earned in a room
+    let room_xp = warp::path!("v1" / "rooms" / String / "xp")
+        .and(warp::get())
+        .and(auth::require_api_key())
         .and(room_manager())
-        .map(|room_id: String, ws: Ws, rooms: RoomManager| {
-            ws.on_upgrade(move |socket| {
-                connect_client(socket, room_id, rooms)
-            })
+        .map(|room_id: String, rooms: RoomManager| {
+            match rooms.get(&room_id) {
+                Some(room) => {
+                    let body = json!({
+                        "room_id": room_id,
+                        "users": room.xp_ledger(),
+                    });
+                    reply::with_status(reply::json(&body), StatusCode::OK)
+                },
+                None => message_reply(StatusCode::NOT_FOUND, "This room does not exist!"),
+            }
+        });
+
+    // GET v1/users/<user_id>/xp/ -> The XP a user has earned in every room
+    let user_xp = warp::path!("v1" / "users" / String / "xp")
+        .and(warp::get())
+        .and(auth::require_api_key())
+        .and(room_manager())
+        .map(|user_id: String, rooms: RoomManager| {
+            let entries = rooms.user_xp(&user_id);
+            let earned: f64 = entries.iter().map(|(_, entry)| entry.earned).sum();
+            let pending: f64 = entries.iter().map(|(_, entry)| entry.pending).sum();
+            let rooms: Vec<_> = entries
+                .into_iter()
+                .map(|(room_id, entry)| json!({
+                    "room_id": room_id,
+                    "watch_time": entry.watch_time,
+                    "earned": entry.earned,
+                    "settled": entry.settled,
+                    "pending": entry.pending,
+                }))
+                .collect();
+
+            reply::json(&json!({
+                "user_id": user_id,
+                "earned": earned,
+                "pending": pending,
+                "rooms": rooms,
+            }))
+        });
+
+    // POST v1/payouts/ -> Pays out and returns every user's pending XP
+    let payouts = warp::path!("v1" / "payouts")
+        .and(warp::post())
+        .and(room_manager())
+        .and(auth::signed_json())
+        .then(|rooms: RoomManager, request: PayoutRequest| async move {
+            if request.payout_id.is_empty() {
+                return message_reply(StatusCode::BAD_REQUEST, "The payout id can not be empty!")
+                    .into_response()
+            }
+
+            match rooms.settle_payouts(request.payout_id).await {
+                Ok(batch) => reply::json(&batch).into_response(),
+                Err(error) => {
+                    tracing::error!(%error, "Failed to save payouts");
+                    message_reply(
+                        StatusCode::SERVICE_UNAVAILABLE,
+                        "The payout could not be saved, retry with the same payout id!",
+                    ).into_response()
+                },
+            }
         });
 
-    // GET /add/<room_id> -> Makes a room
+    // GET /add/<room_id> -> Makes a room, deprecated for POST v1/rooms/
     let add_room = warp::path!("add" / String)
+        .and(auth::require_api_key())
         .and(room_manager())
         .and(warp::query::<ListOptions>())
         .map(|room_id: String, rooms: RoomManager, options: ListOptions| {
-            rooms.create_room(room_id, options.live_server);
+            rooms.create_room(
+                room_id,
+                options.live_server,
+                options.stats_backend,
+                MultiplierKind::default(),
+                None,
+            );
 
-            "Made room!"
+            deprecated("Made room!", "/v1/rooms")
         });
 
-    // GET /remove/<room_id> -> Removes a room
+    // GET /remove/<room_id> -> Removes a room, deprecated for DELETE v1/rooms/<room_id>/
     let remove_room = warp::path!("remove" / String)
+        .and(auth::require_api_key())
         .and(room_manager())
-        .map(|room_id: String, rooms: RoomManager| {
-            rooms.delete_room(room_id);
+        .then(|room_id: String, rooms: RoomManager| async move {
+            let successor = format!("/v1/rooms/{}", &room_id);
+            rooms.delete_room(room_id).await;
 
-            "Removed room!"
+            deprecated("Removed room!", &successor)
         });
 
-    // POST emit/<room_id>/ -> emits a message to a room
+    // POST emit/<room_id>/ -> emits a gateway event to a room, deprecated
+    // for POST v1/rooms/<room_id>/events/. Like before, a body that is not
+    // a gateway event is sent to the room as it is.
     let emit = warp::path!("emit" / String)
         .and(room_manager())
-        .and(warp::body::bytes())
+        .and(auth::signed_body())
         .map(|room_id: String, rooms: RoomManager, body: Bytes| {
-            let msg = if let Some(room) = rooms.get(&room_id) {
-                let msg = String::from_utf8_lossy(body.as_ref());
-                room.send(msg.to_string());
-
-                "Operation complete!"
-            } else {
-                "Unknown room"
+            let (msg, status) = match rooms.get(&room_id) {
+                Some(room) => {
+                    match serde_json::from_slice::<GatewayEvent>(&body) {
+                        Ok(event) => room.emit(event),
+                        Err(_) => room.emit_raw(String::from_utf8_lossy(&body).into_owned()),
+                    }
+                    ("Operation complete!", StatusCode::OK)
+                },
+                None => ("Unknown room", StatusCode::OK),
             };
 
             let mut resp = Response::new(msg.into());
-            let inst = resp.headers_mut();
-            inst.insert(ACCESS_CONTROL_ALLOW_HEADERS,HeaderValue::from_static("*"));
-            inst.insert(ACCESS_CONTROL_ALLOW_ORIGIN,HeaderValue::from_static("*"));
-            inst.insert(ACCESS_CONTROL_ALLOW_METHODS,HeaderValue::from_static("PUT"));
+            *resp.status_mut() = status;
 
-            resp
+            let successor = format!("/v1/rooms/{}/events", &room_id);
+            deprecated(with_cors(resp), &successor)
         });
 
     // GET stats/<room_id>/ -> Gets the full stream stats of the room
     let stats = warp::path!("stats" / String)
+        .and(warp::get())
+        .and(auth::require_api_key())
         .and(room_manager())
         .map(|room_id: String, rooms: RoomManager| {
             if let Some(room) = rooms.get(&room_id) {
@@ -100,25 +373,299 @@ async fn main() {
                 let rep = reply::json(&resp);
                 reply::with_status(rep, StatusCode::OK)
             } else {
-                let test = json!({
-                    "status": 404,
-                    "message": "This room does not exist!"
+                message_reply(StatusCode::NOT_FOUND, "This room does not exist!")
+            }
+        });
+
+    // GET stats/<room_id>/history/ -> Gets the room's stats samples over time
+    let stats_history = warp::path!("stats" / String / "history")
+        .and(warp::get())
+        .and(auth::require_api_key())
+        .and(room_manager())
+        .and(warp::query::<HistoryQuery>())
+        .map(|room_id: String, rooms: RoomManager, query: HistoryQuery| {
+            let from = query.from.unwrap_or(0);
+            let to = query.to.unwrap_or(u64::MAX);
+            if from > to {
+                return message_reply(StatusCode::BAD_REQUEST, "from can not be after to!")
+                    .into_response()
+            }
+
+            match rooms.get(&room_id) {
+                Some(room) => reply::json(&room.get_stats_history(&query)).into_response(),
+                None => message_reply(StatusCode::NOT_FOUND, "This room does not exist!")
+                    .into_response(),
+            }
+        });
+
+    // POST stats/<room_id>/ -> Pushes stream stats to a room's stats source
+    let push_stats = warp::path!("stats" / String)
+        .and(warp::post())
+        .and(room_manager())
+        .and(auth::signed_json())
+        .map(|room_id: String, rooms: RoomManager, pushed: PushedStats| {
+            let status = if pushed.live {
+                StreamStatus::Live(StatsSample {
+                    total_bytes: pushed.total_bytes,
+                    bytes_per_sec: pushed.bytes_per_sec,
+                })
+            } else {
+                StreamStatus::NotLive
+            };
+
+            match rooms.get(&room_id) {
+                Some(room) if room.push_stats(status) => {
+                    message_reply(StatusCode::OK, "Stats pushed!")
+                },
+                Some(_) => {
+                    message_reply(StatusCode::CONFLICT, "This room does not take pushed stats!")
+                },
+                None => message_reply(StatusCode::NOT_FOUND, "This room does not exist!"),
+            }
+        });
+
+    // POST hooks/on_publish/ -> The live server started receiving a stream
+    let on_publish = warp::path!("hooks" / "on_publish")
+        .and(warp::post())
+        .and(room_manager())
+        .and(auth::webhook_form())
+        .map(|rooms: RoomManager, hook: StreamHook| {
+            let room = rooms.get(&hook.name);
+            if let Some(room) = room.as_ref() {
+                room.stream_published();
+            }
+
+            hook_reply(room.is_some())
+        });
+
+    // POST hooks/on_unpublish/ -> The live server stopped receiving a stream
+    let on_unpublish = warp::path!("hooks" / "on_unpublish")
+        .and(warp::post())
+        .and(room_manager())
+        .and(auth::webhook_form())
+        .map(|rooms: RoomManager, hook: StreamHook| {
+            let room = rooms.get(&hook.name);
+            if let Some(room) = room.as_ref() {
+                room.stream_unpublished();
+            }
+
+            hook_reply(room.is_some())
+        });
+
+    // POST hooks/on_stats/ -> The live server's periodic stats for a stream
+    let on_stats = warp::path!("hooks" / "on_stats")
+        .and(warp::post())
+        .and(room_manager())
+        .and(auth::webhook_form())
+        .map(|rooms: RoomManager, hook: StreamHook| {
+            let room = rooms.get(&hook.name);
+            if let Some(room) = room.as_ref() {
+                room.stream_stats(StatsSample {
+                    total_bytes: hook.total_bytes,
+                    bytes_per_sec: hook.bytes_per_sec,
                 });
-                let rep = reply::json(&test);
-                reply::with_status(rep, StatusCode::NOT_FOUND)
             }
+
+            hook_reply(room.is_some())
+        });
+
+    // GET healthz/ -> If the process is answering
+    let healthz = warp::path!("healthz")
+        .and(warp::get())
+        .map(|| {
+            reply::json(&json!({
+                "status": 200,
+                "message": "OK!",
+            }))
+        });
+
+    // GET readyz/ -> If the gateway should be sent traffic, with the checks that decided it,
+    // why a check failed is only given with an admin api key
+    let readyz = warp::path!("readyz")
+        .and(warp::get())
+        .and(room_manager())
+        .and(auth::has_api_key())
+        .then(|rooms: RoomManager, is_admin: bool| async move {
+            let mut readiness = health::readiness(&rooms).await;
+            if !is_admin {
+                readiness.redact();
+            }
+
+            let status = StatusCode::from_u16(readiness.status)
+                .unwrap_or(StatusCode::SERVICE_UNAVAILABLE);
+
+            reply::with_status(reply::json(&readiness), status)
+        });
+
+    // GET metrics/ -> The gateway's metrics in the Prometheus text format
+    let metrics = warp::path!("metrics")
+        .and(warp::get())
+        .and(auth::require_api_key())
+        .and(room_manager())
+        .map(|rooms: RoomManager| {
+            reply::with_header(
+                metrics::render(&rooms),
+                "content-type",
+                "text/plain; version=0.0.4",
+            )
+        });
+
+    // GET logging/ -> The current log format and filter
+    let get_logging = warp::path!("logging")
+        .and(warp::get())
+        .and(auth::require_api_key())
+        .map(|| reply::json(&logging::settings()));
+
+    // PUT logging/ -> Changes the log format and filter
+    let set_logging = warp::path!("logging")
+        .and(warp::put())
+        .and(auth::signed_json())
+        .map(|update: logging::LogUpdate| {
+            match logging::update(update) {
+                Ok(settings) => {
+                    tracing::info!(format = ?settings.format, filter = %settings.filter, "Logging changed");
+                    reply::with_status(reply::json(&settings), StatusCode::OK)
+                },
+                Err(e) => message_reply(StatusCode::BAD_REQUEST, &e.to_string()),
+            }
+        });
+
+    // GET rooms/ -> Lists the rooms, filtered and paginated
+    let list_rooms = warp::path!("rooms")
+        .and(warp::get())
+        .and(auth::require_api_key())
+        .and(room_manager())
+        .and(warp::query::<RoomFilter>())
+        .map(|rooms: RoomManager, filter: RoomFilter| {
+            reply::json(&rooms.list_rooms(&filter))
         });
 
     let routes = gateway
+        .or(push_stats)
+        .or(create_room)
+        .or(delete_room)
+        .or(room_events)
+        .or(room_xp)
+        .or(user_xp)
+        .or(payouts)
         .or(remove_room)
         .or(add_room)
         .or(emit)
-        .or(stats);
+        .or(stats_history)
+        .or(stats)
+        .or(list_rooms)
+        .or(on_publish)
+        .or(on_unpublish)
+        .or(on_stats)
+        .or(metrics)
+        .or(healthz)
+        .or(readyz)
+        .or(get_logging)
+        .or(set_logging)
+        .recover(auth::handle_rejection)
+        .with(warp::trace(|info| tracing::info_span!(
+            "request",
+            method = %info.method(),
+            route = %info.path(),
+        )));
+
+
+    auth::log_key_status();
+    identity::log_validation_mode();
+    let config = config::get();
+    tracing::info!(
+        bind = %config.bind,
+        gateway_url = %config.public_gateway_url,
+        api_url = %config.public_api_url,
+        "Listening",
+    );
+    // The listener is kept open while draining so `/readyz` can report it
+    // and late upgrades are refused rather than left unanswered.
+    let (_, server) = warp::serve(routes)
+        .bind_with_graceful_shutdown(config.bind, async move {
+            shutdown::signal().await;
+            tracing::info!("Shutting down, telling clients to reconnect");
+            signal_rooms.start_shutdown();
+            signal_rooms.finish_shutdown().await;
+        });
+    server.await;
+
+    tracing::info!("Saving rooms");
+    if let Err(error) = shutdown_rooms.save_rooms().await {
+        tracing::error!(%error, "Failed to save rooms");
+    }
+}
+
+
+/// Decodes a gateway event and emits it to a room, giving the status and
+/// message to fail with if the room does not exist or the event is invalid.
+fn emit_event(
+    rooms: &RoomManager,
+    room_id: &str,
+    body: &[u8],
+) -> Result<(), (StatusCode, &'static str)> {
+    let room = rooms
+        .get(room_id)
+        .ok_or((StatusCode::NOT_FOUND, "This room does not exist!"))?;
 
+    let event = serde_json::from_slice::<GatewayEvent>(body)
+        .map_err(|_| (StatusCode::BAD_REQUEST, "Invalid gateway event!"))?;
 
-    println!("[ SERVER INFO ] Gateway running @ wss://gateway.spooderfy.com/ws");
-    println!("[ SERVER INFO ] Api running @ https://gateway.spooderfy.com");
-    warp::serve(routes).run(([0, 0, 0, 0], 3030)).await;
+    room.emit(event);
+    Ok(())
 }
 
 
+/// The standard JSON body of a status and message.
+fn message_reply(status: StatusCode, message: &str) -> reply::WithStatus<reply::Json> {
+    let body = json!({
+        "status": status.as_u16(),
+        "message": message,
+    });
+    reply::with_status(reply::json(&body), status)
+}
+
+
+/// Adds the configured CORS headers to a response from the emit routes.
+fn with_cors(mut resp: Response) -> Response {
+    let config = config::get();
+    let inst = resp.headers_mut();
+    for (header, value) in [
+        (ACCESS_CONTROL_ALLOW_HEADERS, &config.cors_allow_headers),
+        (ACCESS_CONTROL_ALLOW_ORIGIN, &config.cors_allow_origin),
+        (ACCESS_CONTROL_ALLOW_METHODS, &config.cors_allow_methods),
+    ] {
+        if let Ok(value) = HeaderValue::from_str(value) {
+            inst.insert(header, value);
+        }
+    }
+
+    resp
+}
+
+
+/// Marks the response of an old route as deprecated, pointing to the
+/// route that replaces it.
+fn deprecated(reply: impl Reply, successor: &str) -> Response {
+    let mut resp = reply.into_response();
+    let headers = resp.headers_mut();
+    headers.insert("deprecation", HeaderValue::from_static("true"));
+    if let Ok(link) = HeaderValue::from_str(&format!("<{}>; rel=\"successor-version\"", successor)) {
+        headers.insert(LINK, link);
+    }
+
+    resp
+}
+
+
+/// The response to a webhook from the live server.
+///
+/// Unknown rooms get a 404 which makes nginx-rtmp refuse to publish
+/// the stream.
+fn hook_reply(found: bool) -> impl warp::Reply {
+    if found {
+        message_reply(StatusCode::OK, "Hook received!")
+    } else {
+        message_reply(StatusCode::NOT_FOUND, "This room does not exist!")
+    }
+}
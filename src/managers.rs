@@ -1,32 +1,65 @@
-use warp::http::StatusCode;
-use tokio::sync::broadcast;
-use tokio::time::{self, Duration};
+use tokio::sync::{broadcast, watch};
+use tokio::time::{self, Duration, Instant};
 use tokio::task::JoinHandle;
 
 use dashmap::DashMap;
 use dashmap::mapref::one::Ref;
 
-use serde_json::Value;
+use futures::FutureExt;
+use futures::future;
+use rand::Rng;
+
 use serde::{Serialize, Deserialize};
+use tracing::{Instrument, Span};
 
-use std::sync::Arc;
+use std::cmp::Reverse;
+use std::sync::{Arc, Mutex};
+use std::collections::VecDeque;
 use std::collections::hash_map::RandomState;
-use std::sync::atomic::{AtomicUsize, AtomicBool};
+use std::sync::atomic::{AtomicUsize, AtomicU64};
 use std::sync::atomic::Ordering::Relaxed;
-use std::env;
 
-use crate::opcodes;
+use crate::analytics::{StreamSummary, ViewerAnalytics, ViewerStats};
+use crate::bandwidth::{self, BandwidthState, LimitScope, Violation};
+use crate::events::{
+    BandwidthWarning, GatewayEvent, LiveEnded, LiveReady, LiveStalled, LiveStarting, PresenceSnapshot, Reconnect,
+    Resync,
+};
+use crate::identity::UserProfile;
+use crate::ledger::{Ledger, LedgerEntry, Payout, PayoutBatch, PayoutLog};
+use crate::lifecycle::{Lifecycle, StreamState};
+use crate::multiplier::{MultiplierContext, MultiplierKind, MultiplierPolicy};
+use crate::presence::Presence;
+use crate::resume::{EventHistory, RoomEvent, Sessions};
+use crate::series::{HistoryQuery, StatsHistory, StatsPoint, StatsSeries};
+use crate::watcher::{WatcherExit, WatcherHealth};
+use crate::stats::{StatsBackend, StatsSample, StatsSource, StreamStatus};
+use crate::store::{RetiredLedger, RoomSnapshot, RoomStore, StoreError, StoreState};
+use crate::shutdown::{Phase, Shutdown};
+use crate::metrics;
+use crate::config;
 use crate::utils;
 
-pub type RoomSender = broadcast::Sender<String>;
-pub type RoomReceiver = broadcast::Receiver<String>;
+pub type RoomSender = broadcast::Sender<Arc<RoomEvent>>;
+pub type RoomReceiver = broadcast::Receiver<Arc<RoomEvent>>;
 
+/// The amount of rooms listed per page if no limit is given.
+const DEFAULT_PAGE_SIZE: usize = 50;
 
-lazy_static! {
-    static ref API_KEY: String = {
-        env::var("API_KEY").unwrap_or_else(|_| "".to_string())
-    };
-}
+/// The max amount of rooms that can be listed in one page.
+const MAX_PAGE_SIZE: usize = 200;
+
+/// How long connections are given to send their close frames once the
+/// drain period is over.
+const CLOSE_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// The amount of recent sampled rates a stream's average rate is worked
+/// out from.
+const AVERAGE_RATE_SAMPLES: usize = 120;
+
+/// How long stats watchers are given to finish a poll they are in the
+/// middle of when shutting down.
+const WATCHER_STOP_TIMEOUT: Duration = Duration::from_secs(5);
 
 
 /// A controller actor that manages room creation and deletion for
@@ -34,72 +67,620 @@ lazy_static! {
 #[derive(Clone)]
 pub struct RoomManager {
     rooms: Arc<DashMap<String, Room>>,
-    room_watchers: Arc<DashMap<String, JoinHandle<()>>>
+    room_watchers: Arc<DashMap<String, JoinHandle<()>>>,
+    store: Arc<dyn RoomStore>,
+    shutdown: Shutdown,
+    connections: Arc<AtomicUsize>,
+
+    /// Why the store last failed to load or save, None if it succeeded.
+    store_error: Arc<Mutex<Option<String>>>,
+
+    /// Held while saving so two saves never write the store at once or
+    /// an older save lands after a newer one.
+    saving: Arc<tokio::sync::Mutex<()>>,
+
+    /// The recent XP payout batches.
+    payouts: Arc<Mutex<PayoutLog>>,
+
+    /// The XP ledgers of deleted rooms that still had XP waiting to be
+    /// paid out, kept until the next payout.
+    retired_ledgers: Arc<Mutex<Vec<RetiredLedger>>>,
 }
 
 impl RoomManager {
     /// Creates and starts the actor returning a handle to
-    /// communicate with the actor.
-    pub fn new() -> Self {
+    /// communicate with the actor, rooms are persisted to the given store.
+    pub fn new(store: Arc<dyn RoomStore>) -> Self {
         Self {
             rooms: Arc::new(DashMap::new()),
             room_watchers: Arc::new(DashMap::new()),
+            store,
+            shutdown: Shutdown::new(),
+            connections: Arc::new(AtomicUsize::new(0)),
+            store_error: Arc::new(Mutex::new(None)),
+            saving: Arc::new(tokio::sync::Mutex::new(())),
+            payouts: Arc::new(Mutex::new(PayoutLog::new())),
+            retired_ledgers: Arc::new(Mutex::new(Vec::new())),
         }
     }
 
-    /// Creates a room with a given ID.
-    pub fn create_room(&self, room_id: String, live_server: String) {
+    /// Creates a room with a given ID, its stream stats are fetched with
+    /// the given backend and its multiplier worked out with the given
+    /// policy. The room's stream is limited to `byte_rate_limit` bytes per
+    /// second or `ROOM_BYTE_RATE_LIMIT` if not given.
+    ///
+    /// Returns false without changing anything if the room already exists.
+    pub fn create_room(
+        &self,
+        room_id: String,
+        live_server: String,
+        stats_backend: StatsBackend,
+        multiplier_policy: MultiplierKind,
+        byte_rate_limit: Option<usize>,
+    ) -> bool {
         if self.rooms.get(&room_id).is_some() {
-            return
+            return false
         }
 
-        let (tx, _) = broadcast::channel(50);
-        let room = Room {
-            room_id: Arc::new(room_id.clone()),
-            live_server: Arc::new(live_server),
-            sender: tx,
-            members: Arc::new(AtomicUsize::new(0)),
-            multiplier: Arc::new(AtomicUsize::new(0)),
-            avg_byte_rate: Arc::new(AtomicUsize::new(0)),
-            data_streamed: Arc::new(AtomicUsize::new(0)),
-            last_time_sample: Arc::new(AtomicUsize::new(0)),
-            stream_time: Arc::new(AtomicUsize::new(0)),
-            is_live: Arc::new(AtomicBool::new(false))
-        };
+        let room = Room::new(
+            room_id,
+            live_server,
+            stats_backend,
+            multiplier_policy,
+            byte_rate_limit,
+        );
+        self.start_room(room);
+        true
+    }
 
-        // why are you doing this??
-        let room2 = room.clone();
-        let handle = tokio::spawn(room2.watch_stats());
+    /// Adds a room and starts its stats watcher.
+    fn start_room(&self, room: Room) {
+        let room_id = room.room_id.to_string();
+
+        let handle = room.spawn_watcher(self.shutdown.subscribe());
 
         self.rooms.insert(room_id.clone(), room);
         self.room_watchers.insert(room_id, handle);
     }
 
+    /// Rebuilds the rooms saved in the store and restarts their watchers,
+    /// returning how many were restored. The payout log and the ledgers of
+    /// deleted rooms are restored with them.
+    pub async fn load_rooms(&self) -> Result<usize, StoreError> {
+        let state = self.store_result(self.store.load().await)?;
+        let count = state.rooms.len();
+
+        *self.payouts.lock().unwrap() = PayoutLog::restore(state.payouts);
+        self.retired_ledgers.lock().unwrap().extend(state.retired_ledgers);
+
+        for snapshot in state.rooms {
+            if self.rooms.get(&snapshot.room_id).is_some() {
+                continue
+            }
+
+            self.start_room(Room::restore(snapshot));
+        }
+
+        Ok(count)
+    }
+
+    /// Saves every room, the payout log and the ledgers of deleted rooms
+    /// to the store.
+    pub async fn save_rooms(&self) -> Result<(), StoreError> {
+        let _saving = self.saving.lock().await;
+
+        let state = StoreState {
+            rooms: self.rooms
+                .iter()
+                .map(|room| room.snapshot())
+                .collect(),
+            payouts: self.payouts.lock().unwrap().batches(),
+            retired_ledgers: self.retired_ledgers.lock().unwrap().clone(),
+        };
+
+        let result = self.store.save(state).await;
+        self.store_result(result)
+    }
+
+    /// Remembers if the store last failed so readiness can report it.
+    fn store_result<T>(&self, result: Result<T, StoreError>) -> Result<T, StoreError> {
+        *self.store_error.lock().unwrap() = result
+            .as_ref()
+            .err()
+            .map(|e| e.to_string());
+
+        result
+    }
+
+    /// Checks the store can be saved to and did not fail last time it
+    /// was used.
+    pub async fn check_store(&self) -> Result<(), String> {
+        if let Some(e) = self.store_error.lock().unwrap().clone() {
+            return Err(e)
+        }
+
+        self.store.check().await.map_err(|e| e.to_string())
+    }
+
+    /// Checks every room's state can still be locked, a panic while
+    /// holding one of a room's locks leaves it unusable.
+    pub fn check_rooms(&self) -> Result<(), String> {
+        let broken: Vec<String> = self.rooms
+            .iter()
+            .filter(|room| room.is_poisoned())
+            .map(|room| room.key().clone())
+            .collect();
+
+        if broken.is_empty() {
+            Ok(())
+        } else {
+            Err(format!("rooms with poisoned state: {}", broken.join(", ")))
+        }
+    }
+
+    /// Every distinct live server the rooms use.
+    pub fn live_servers(&self) -> Vec<String> {
+        let mut servers: Vec<String> = self.rooms
+            .iter()
+            .map(|room| room.live_server.to_string())
+            .collect();
+
+        servers.sort();
+        servers.dedup();
+        servers
+    }
+
+    /// If the gateway has started shutting down and is refusing new
+    /// connections.
+    pub fn is_draining(&self) -> bool {
+        self.shutdown.is_draining()
+    }
+
+    /// Subscribes to the gateway's shutdown phase.
+    pub fn shutdown_phase(&self) -> watch::Receiver<Phase> {
+        self.shutdown.subscribe()
+    }
+
+    /// The amount of open websocket connections.
+    pub fn connection_count(&self) -> usize {
+        self.connections.load(Relaxed)
+    }
+
+    /// The values of every room exported as metrics.
+    pub fn metrics(&self) -> Vec<RoomMetrics> {
+        self.rooms
+            .iter()
+            .map(|room| room.get_metrics())
+            .collect()
+    }
+
+    /// Counts a websocket connection until the returned guard is dropped.
+    pub fn track_connection(&self) -> ConnectionGuard {
+        self.connections.fetch_add(1, Relaxed);
+        ConnectionGuard {
+            connections: self.connections.clone(),
+        }
+    }
+
+    /// Starts shutting down, new connections are refused, the stats
+    /// watchers stop at their next sleep and every room is told to
+    /// reconnect.
+    pub fn start_shutdown(&self) {
+        self.shutdown.set(Phase::Draining);
+
+        let delay = config::get().reconnect_delay * 1000;
+        for room in self.rooms.iter() {
+            let jitter = rand::thread_rng().gen_range(0..=delay);
+            room.emit(GatewayEvent::Reconnect(Reconnect {
+                delay: delay + jitter,
+            }));
+        }
+    }
+
+    /// Gives clients `SHUTDOWN_DRAIN` seconds to disconnect, closes any
+    /// that are left and waits for the stats watchers to stop.
+    pub async fn finish_shutdown(&self) {
+        let drain = Duration::from_secs(config::get().shutdown_drain);
+        tracing::info!(
+            connections = self.connections.load(Relaxed),
+            drain = %utils::humanize(drain),
+            "Draining connections",
+        );
+        self.wait_for_connections(drain).await;
+
+        self.shutdown.set(Phase::Closing);
+        self.wait_for_connections(CLOSE_TIMEOUT).await;
+
+        let room_ids: Vec<String> = self.room_watchers
+            .iter()
+            .map(|handle| handle.key().clone())
+            .collect();
+        let handles = room_ids
+            .iter()
+            .filter_map(|room_id| self.room_watchers.remove(room_id))
+            .map(|(_, handle)| handle);
+
+        if time::timeout(WATCHER_STOP_TIMEOUT, future::join_all(handles)).await.is_err() {
+            tracing::warn!("Some stats watchers did not stop in time");
+        }
+    }
+
+    /// Waits until every connection has closed or the timeout passes.
+    async fn wait_for_connections(&self, timeout: Duration) {
+        let _ = time::timeout(timeout, async {
+            while self.connections.load(Relaxed) > 0 {
+                time::sleep(Duration::from_millis(100)).await;
+            }
+        }).await;
+    }
+
+    /// Saves every room to the store every `STORE_INTERVAL` seconds.
+    pub async fn persist_rooms(self) {
+        let mut interval = time::interval(Duration::from_secs(config::get().store_interval));
+        interval.tick().await;
+
+        loop {
+            interval.tick().await;
+            if let Err(error) = self.save_rooms().await {
+                tracing::error!(%error, "Failed to save rooms");
+            }
+        }
+    }
+
     /// Deletes a room with a given ID.
-    pub fn delete_room(&self, room_id: String) {
+    ///
+    /// The room is removed before its watcher so the supervisor can not
+    /// restart the watcher of a room that is being deleted. The store is
+    /// saved straight away so the room does not come back if the gateway
+    /// restarts before the next `STORE_INTERVAL`.
+    ///
+    /// Returns false if there was no room to delete.
+    pub async fn delete_room(&self, room_id: String) -> bool {
+        let removed = self.rooms.remove(&room_id);
         if let Some((_, handle)) = self.room_watchers.remove(&room_id) {
             handle.abort();
         };
-        self.rooms.remove(&room_id);
-        println!("[ ROOM {} ] Room closing and terminating connections", &room_id);
+
+        let (_, room) = match removed {
+            Some(removed) => removed,
+            None => return false,
+        };
+
+        tracing::info!(%room_id, "Room closing and terminating connections");
+        room.close();
+
+        let mut ledger = room.ledger.lock().unwrap().clone();
+        ledger.set_live(false);
+        if ledger.has_pending() {
+            self.retired_ledgers.lock().unwrap().push(RetiredLedger { room_id, ledger });
+        }
+
+        if let Err(error) = self.save_rooms().await {
+            tracing::error!(%error, "Failed to save rooms");
+        }
+
+        true
+    }
+
+    /// Every room's XP ledger entries for a user, skipping rooms they
+    /// have never watched.
+    pub fn user_xp(&self, user_id: &str) -> Vec<(String, LedgerEntry)> {
+        let mut entries: Vec<(String, LedgerEntry)> = self.rooms
+            .iter()
+            .filter_map(|room| {
+                let entry = room.ledger.lock().unwrap().entry(user_id)?;
+                Some((room.key().clone(), entry))
+            })
+            .collect();
+
+        entries.sort_by(|a, b| a.0.cmp(&b.0));
+        entries
+    }
+
+    /// Pays out every user's pending XP in every room.
+    ///
+    /// This can be retried safely, a payout id that has been used before
+    /// gets the batch it made back without settling anything again as long
+    /// as it is one of the last `PAYOUT_HISTORY` batches.
+    ///
+    /// The batch is only returned once it and the settled ledgers have been
+    /// saved to the store, so a payout is never made twice across a restart.
+    pub async fn settle_payouts(&self, payout_id: String) -> Result<PayoutBatch, StoreError> {
+        let batch = self.settle_ledgers(payout_id);
+        self.save_rooms().await?;
+        Ok(batch)
+    }
+
+    /// Settles every ledger into a batch, or gets the batch already made
+    /// with the id.
+    fn settle_ledgers(&self, payout_id: String) -> PayoutBatch {
+        let mut log = self.payouts.lock().unwrap();
+        if let Some(batch) = log.get(&payout_id) {
+            return batch.clone()
+        }
+
+        let retired = std::mem::take(&mut *self.retired_ledgers.lock().unwrap());
+        let settled = self.rooms
+            .iter()
+            .map(|room| (room.key().clone(), room.ledger.lock().unwrap().settle()))
+            .chain(retired.into_iter().map(|mut retired| (retired.room_id, retired.ledger.settle())));
+
+        let mut payouts = Vec::new();
+        for (room_id, users) in settled {
+            for (user_id, xp) in users {
+                payouts.push(Payout {
+                    room_id: room_id.clone(),
+                    user_id,
+                    xp,
+                });
+            }
+        }
+
+        payouts.sort_by(|a, b| (&a.room_id, &a.user_id).cmp(&(&b.room_id, &b.user_id)));
+
+        let batch = PayoutBatch {
+            payout_id,
+            created_at: utils::now_millis(),
+            payouts,
+        };
+        log.push(batch.clone());
+
+        batch
+    }
+
+    /// Checks on every room's stats watcher every `SUPERVISOR_INTERVAL`
+    /// seconds, restarting any that have exited, until the gateway starts
+    /// shutting down. The global bandwidth limit is checked and every
+    /// room's multiplier worked out again at the same time.
+    pub async fn supervise_watchers(self) {
+        let mut interval = time::interval(Duration::from_secs(config::get().supervisor_interval));
+
+        loop {
+            interval.tick().await;
+            if self.shutdown.is_draining() {
+                return
+            }
+
+            self.check_watchers();
+            self.check_bandwidth(config::get().global_byte_rate_limit);
+
+            for room in self.rooms.iter() {
+                room.refresh_multiplier();
+            }
+        }
+    }
+
+    /// Warns the rooms pushing every live room's combined rate over the
+    /// global limit, starting with the biggest stream and going down until
+    /// the rest fit under it. A limit of 0 is no limit.
+    fn check_bandwidth(&self, limit: usize) {
+        if limit == 0 {
+            return
+        }
+
+        let mut live: Vec<(usize, Room)> = self.rooms
+            .iter()
+            .filter(|room| room.lifecycle.lock().unwrap().state.is_live())
+            .map(|room| (room.bandwidth.lock().unwrap().rate(), room.clone()))
+            .collect();
+        live.sort_by_key(|(rate, _)| Reverse(*rate));
+
+        let combined: usize = live.iter().map(|(rate, _)| rate).sum();
+        let mut total = combined;
+        for (rate, room) in live {
+            if total > limit {
+                room.check_bandwidth(LimitScope::Global, combined, limit);
+                total -= rate;
+            } else {
+                room.bandwidth.lock().unwrap().clear(LimitScope::Global);
+            }
+        }
+    }
+
+    /// Records any watchers that have finished or panicked and restarts
+    /// the ones whose backoff has passed.
+    fn check_watchers(&self) {
+        for room in self.rooms.iter() {
+            let is_finished = self.room_watchers
+                .get(room.key())
+                .map(|handle| handle.is_finished())
+                .unwrap_or(false);
+
+            if is_finished {
+                if let Some((_, handle)) = self.room_watchers.remove(room.key()) {
+                    let panicked = match handle.now_or_never() {
+                        Some(Err(e)) => e.is_panic(),
+                        _ => false,
+                    };
+
+                    room.watcher_exited(panicked);
+                }
+            }
+
+            let restart_due = room.watcher.lock().unwrap().restart_due();
+            if restart_due {
+                room.span.in_scope(|| tracing::info!("Restarting stats watcher"));
+
+                room.watcher.lock().unwrap().restarted();
+                let handle = room.spawn_watcher(self.shutdown.subscribe());
+                self.room_watchers.insert(room.key().clone(), handle);
+            }
+        }
+    }
+
+    /// Gets the overview of a single room.
+    pub fn get_summary(&self, room_id: &str) -> Option<RoomSummary> {
+        self.rooms.get(room_id).map(|room| self.summarize(&room))
+    }
+
+    fn summarize(&self, room: &Room) -> RoomSummary {
+        let watcher_running = self.room_watchers
+            .get(room.room_id.as_str())
+            .map(|handle| !handle.is_finished())
+            .unwrap_or(false);
+
+        room.get_summary(watcher_running)
+    }
+
+    /// Lists the rooms matching the filter, sorted by room id so pages
+    /// stay stable between requests.
+    pub fn list_rooms(&self, filter: &RoomFilter) -> RoomList {
+        let mut rooms: Vec<RoomSummary> = self.rooms
+            .iter()
+            .map(|room| self.summarize(&room))
+            .filter(|summary| filter.matches(summary))
+            .collect();
+
+        rooms.sort_by(|a, b| a.room_id.cmp(&b.room_id));
+
+        let total = rooms.len();
+        let offset = filter.offset.unwrap_or(0);
+        let limit = filter.limit
+            .unwrap_or(DEFAULT_PAGE_SIZE)
+            .min(MAX_PAGE_SIZE);
+
+        let rooms = rooms
+            .into_iter()
+            .skip(offset)
+            .take(limit)
+            .collect();
+
+        RoomList {
+            total,
+            offset,
+            limit,
+            rooms,
+        }
     }
 
     /// Gets a room with a given id as a immutable referance.
-    pub fn get(&self, room_id: &String) -> Option<Ref<String, Room, RandomState>> {
+    pub fn get(&self, room_id: &str) -> Option<Ref<'_, String, Room, RandomState>> {
         self.rooms.get(room_id)
     }
 }
 
 
-/// A message to the websocket
+/// A room's values exported as metrics.
+pub struct RoomMetrics {
+    pub room_id: String,
+    pub is_live: bool,
+    pub sockets: usize,
+    pub members: usize,
+    pub avg_byte_rate: usize,
+    pub data_streamed: usize,
+    pub stream_time: usize,
+}
+
+
+/// Keeps a websocket connection counted while it is alive.
+pub struct ConnectionGuard {
+    connections: Arc<AtomicUsize>,
+}
+
+impl Drop for ConnectionGuard {
+    fn drop(&mut self) {
+        self.connections.fetch_sub(1, Relaxed);
+    }
+}
+
+
+/// The filters and pagination for listing rooms.
+#[derive(Debug, Deserialize)]
+pub struct RoomFilter {
+    /// Only list rooms that are / are not live.
+    pub live: Option<bool>,
+
+    /// Only list rooms with at least this many members.
+    pub min_members: Option<usize>,
+
+    /// Only list rooms with at most this many members.
+    pub max_members: Option<usize>,
+
+    /// Only list rooms using this multiplier policy e.g. `steps`.
+    pub policy: Option<String>,
+
+    /// The amount of matching rooms to skip.
+    pub offset: Option<usize>,
+
+    /// The max amount of rooms to return, capped at `MAX_PAGE_SIZE`.
+    pub limit: Option<usize>,
+}
+
+impl RoomFilter {
+    /// If the room matches every filter that was given.
+    fn matches(&self, summary: &RoomSummary) -> bool {
+        let live_ok = self.live
+            .map(|live| live == summary.is_live)
+            .unwrap_or(true);
+        let min_ok = self.min_members
+            .map(|min| summary.members >= min)
+            .unwrap_or(true);
+        let max_ok = self.max_members
+            .map(|max| summary.members <= max)
+            .unwrap_or(true);
+        let policy_ok = self.policy
+            .as_ref()
+            .map(|policy| policy == summary.multiplier_policy.as_str())
+            .unwrap_or(true);
+
+        live_ok & min_ok & max_ok & policy_ok
+    }
+}
+
+
+/// A page of rooms.
 #[derive(Serialize)]
-pub struct WsMessage {
-    /// The websocket opcode
-    opcode: usize,
+pub struct RoomList {
+    /// The amount of rooms matching the filter across every page.
+    total: usize,
+
+    /// The amount of matching rooms skipped.
+    offset: usize,
+
+    /// The max amount of rooms in this page.
+    limit: usize,
+
+    /// The rooms in this page.
+    rooms: Vec<RoomSummary>,
+}
+
+
+/// An overview of a room for listing.
+#[derive(Serialize)]
+pub struct RoomSummary {
+    /// The room id.
+    room_id: String,
 
-    /// The payload of the message, if the opcode does not require
-    /// a body this can be left blank.
-    payload: Option<Value>,
+    /// The live server url.
+    live_server: String,
+
+    /// If the room's stream is live.
+    is_live: bool,
+
+    /// Where the room's stream is in its lifecycle.
+    state: StreamState,
+
+    /// The amount of members in the room.
+    members: usize,
+
+    /// The multiplier in it's floating point form.
+    multiplier: f64,
+
+    /// When the room was created as a unix timestamp in ms.
+    created_at: u64,
+
+    /// The kind of source the room's stream stats come from.
+    stats_backend: StatsBackend,
+
+    /// How the room's multiplier is worked out.
+    multiplier_policy: MultiplierKind,
+
+    /// The most bytes per second the room's stream may average over its
+    /// last few samples, None if it is not limited.
+    byte_rate_limit: Option<usize>,
+
+    /// If the room's stats watcher task is still running.
+    watcher_running: bool,
 }
 
 
@@ -108,22 +689,34 @@ pub struct WsMessage {
 /// This is pretty much only for gateway clients when someone joins or
 /// disconnects from the room. Most things want FullStats for a more
 /// detailed incite into the room.
-#[derive(Serialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct BasicStats {
     /// The amount of members in the room.
     members: usize,
 
-    /// The multiplier in n.nx format e.g. '1.1x'
+    /// The multiplier in n.nx format e.g. '1.1x', or n.nnx if the
+    /// room's policy gives a finer multiplier e.g. '1.25x'.
     multiplier: String,
 }
 
+
+/// Formats a multiplier with one decimal place, or two if it needs it.
+fn format_multiplier(multiplier: f64) -> String {
+    let formatted = format!("{:.2}", multiplier);
+    let formatted = formatted
+        .strip_suffix('0')
+        .unwrap_or(&formatted);
+
+    format!("{}x", formatted)
+}
+
 #[derive(Serialize)]
 pub struct FullStats {
     /// The amount of members in the room.
     members: usize,
 
     /// The multiplier in it's floating point form.
-    multiplier: f32,
+    multiplier: f64,
 
     /// The total amount of bytes streamed.
     ///
@@ -144,48 +737,83 @@ pub struct FullStats {
     /// for / limit the time of the stream but for calculation that can
     /// be more rough / random.
     avg_stream_time: usize,
-}
 
+    /// Every unique user currently in the room.
+    presence: Vec<UserProfile>,
+
+    /// The amount of times a client fell behind the broadcast channel.
+    lag_events: usize,
+
+    /// The total amount of messages clients missed by falling behind.
+    lagged_messages: usize,
+
+    /// The health of the room's stats watcher.
+    watcher: WatcherHealth,
 
-/// The streaming server stats response
-#[allow(unused)]
-#[derive(Deserialize)]
-struct StreamStatsResponse {
-    /// The http status.
-    status: u16,
+    /// Where the room's stream is in its lifecycle.
+    stream: Lifecycle,
 
-    /// Any relevant info returned by the response.
-    data: Value,
+    /// The most bytes per second the room's stream may average over its
+    /// last few samples, None if it is not limited.
+    byte_rate_limit: Option<usize>,
+
+    /// The times the room's stream went over a bandwidth limit.
+    bandwidth: BandwidthState,
+
+    /// Who has watched the current or most recent stream.
+    viewers: ViewerStats,
+
+    /// The summary of the most recent stream to end.
+    last_stream: Option<StreamSummary>,
 }
 
 
-/// The stream statistics.
-///
-/// This just wraps the resulting JSON given by the live streaming server
-/// and changes constantly.
-#[allow(unused)]
-#[derive(Deserialize)]
-struct StreamStats {
-    /// The key/room_name string that the server identifies a session with.
-    key: String,
+/// The last `AVERAGE_RATE_SAMPLES` rates sampled from a stream.
+#[derive(Default)]
+struct ByteRates {
+    /// The rates in the order they were sampled, oldest first.
+    samples: VecDeque<usize>,
 
-    /// The RTMP input url to publish data to the server.
-    url: String,
+    /// The same rates from lowest to highest.
+    sorted: Vec<usize>,
+}
 
-    /// The uid of a stream publisher.
-    stream_id: u32,
+impl ByteRates {
+    /// Adds a sampled rate, dropping the oldest if there are too many,
+    /// and returns the new average.
+    fn push(&mut self, rate: usize) -> usize {
+        if self.samples.len() >= AVERAGE_RATE_SAMPLES {
+            if let Some(oldest) = self.samples.pop_front() {
+                if let Ok(index) = self.sorted.binary_search(&oldest) {
+                    self.sorted.remove(index);
+                }
+            }
+        }
 
-    /// The total amount of bytes sent to the server in video form.
-    video_total_bytes: usize,
+        self.samples.push_back(rate);
+        let index = self.sorted.partition_point(|sorted| *sorted < rate);
+        self.sorted.insert(index, rate);
 
-    /// The bitrate of the video in bytes/sec.
-    video_speed: usize,  // todo maybe rename this???
+        self.average()
+    }
 
-    /// The total amount of bytes sent to the data in audio form.
-    audio_total_bytes: usize,
+    /// The mean of the middle half of the rates so a few odd samples do
+    /// not throw it off, 0 if there are none.
+    fn average(&self) -> usize {
+        let side_split = self.sorted.len() / 4;
+        let sliced = &self.sorted[side_split..self.sorted.len() - side_split];
+        sliced
+            .iter()
+            .sum::<usize>()
+            .checked_div(sliced.len())
+            .unwrap_or(0)
+    }
 
-    /// The bitrate of the audio in bytes/sec.
-    audio_speed: usize,  // todo maybe rename this???
+    /// Forgets the previous stream's rates.
+    fn clear(&mut self) {
+        self.samples.clear();
+        self.sorted.clear();
+    }
 }
 
 
@@ -198,18 +826,48 @@ pub struct Room {
     /// The live server url
     pub(crate) live_server: Arc<String>,
 
+    /// When the room was created as a unix timestamp in ms.
+    created_at: u64,
+
+    /// The kind of source the room's stream stats come from.
+    stats_backend: StatsBackend,
+
+    /// Where the room's stream stats come from.
+    stats_source: Arc<dyn StatsSource>,
+
     /// The message broadcasting channel.
     sender: RoomSender,
 
     /// The amount of members in the room.
     members: Arc<AtomicUsize>,
 
+    /// The amount of websocket connections that have joined the room.
+    sockets: Arc<AtomicUsize>,
+
+    /// The users connected to the room.
+    presence: Arc<Mutex<Presence>>,
+
+    /// The recent events broadcast to the room.
+    history: Arc<Mutex<EventHistory>>,
+
+    /// The sessions that can be resumed.
+    sessions: Arc<Mutex<Sessions>>,
+
     /// The xp multiplier for the room.
-    multiplier: Arc<AtomicUsize>,
+    multiplier: Arc<Mutex<f64>>,
+
+    /// The kind of policy the multiplier is worked out with.
+    multiplier_kind: MultiplierKind,
+
+    /// Works out the multiplier.
+    multiplier_policy: Arc<dyn MultiplierPolicy>,
 
     /// The average bitrate beings sent to the server.
     avg_byte_rate: Arc<AtomicUsize>,
 
+    /// The stream's recent sampled rates.
+    byte_rates: Arc<Mutex<ByteRates>>,
+
     /// The total amount of bytes streamed to the server.
     data_streamed: Arc<AtomicUsize>,
 
@@ -219,14 +877,461 @@ pub struct Room {
     /// Approx length of streaming time in seconds.
     stream_time: Arc<AtomicUsize>,
 
-    /// A bool representing if the stream is live or not.
-    pub(crate) is_live: Arc<AtomicBool>,
+    /// The room's recent stats samples.
+    series: Arc<Mutex<StatsSeries>>,
+
+    /// The room's own bandwidth limit, `ROOM_BYTE_RATE_LIMIT` is used
+    /// if not set.
+    byte_rate_limit: Option<usize>,
+
+    /// The times the room's stream went over a bandwidth limit.
+    bandwidth: Arc<Mutex<BandwidthState>>,
+
+    /// Where the room's stream is in its lifecycle.
+    lifecycle: Arc<Mutex<Lifecycle>>,
+
+    /// When the live server last called a webhook for the room as a unix
+    /// timestamp in ms, 0 if it never has.
+    last_webhook_at: Arc<AtomicU64>,
+
+    /// The amount of times a client fell behind the broadcast channel.
+    lag_events: Arc<AtomicUsize>,
+
+    /// The total amount of messages clients missed by falling behind.
+    lagged_messages: Arc<AtomicUsize>,
+
+    /// The health of the room's stats watcher.
+    watcher: Arc<Mutex<WatcherHealth>>,
+
+    /// The XP each user has earned watching the room.
+    ledger: Arc<Mutex<Ledger>>,
+
+    /// Who has watched the room's streams.
+    analytics: Arc<Mutex<ViewerAnalytics>>,
+
+    /// Set once the room has been deleted, its connections are closed
+    /// when it is.
+    closed: Arc<watch::Sender<bool>>,
+
+    /// The span the room's logs are recorded in.
+    span: Span,
 }
 
 impl Room {
-    /// Sends a message to the broadcast channel.
-    pub fn send(&self, msg: String) {
-        let _ = self.sender.send(msg);
+    /// Creates a room with nothing streamed to it yet.
+    fn new(
+        room_id: String,
+        live_server: String,
+        stats_backend: StatsBackend,
+        multiplier_kind: MultiplierKind,
+        byte_rate_limit: Option<usize>,
+    ) -> Self {
+        let (tx, _) = broadcast::channel(config::get().broadcast_capacity);
+        let span = tracing::info_span!(
+            parent: None,
+            "room",
+            room_id = %room_id,
+            backend = stats_backend.as_str(),
+        );
+
+        Self {
+            room_id: Arc::new(room_id.clone()),
+            stats_source: stats_backend.build(&live_server, &room_id),
+            stats_backend,
+            live_server: Arc::new(live_server),
+            created_at: utils::now_millis(),
+            sender: tx,
+            members: Arc::new(AtomicUsize::new(0)),
+            sockets: Arc::new(AtomicUsize::new(0)),
+            presence: Arc::new(Mutex::new(Presence::default())),
+            history: Arc::new(Mutex::new(EventHistory::new(config::get().resume_buffer_size))),
+            sessions: Arc::new(Mutex::new(Sessions::default())),
+            multiplier: Arc::new(Mutex::new(1.0)),
+            multiplier_policy: multiplier_kind.build(),
+            multiplier_kind,
+            avg_byte_rate: Arc::new(AtomicUsize::new(0)),
+            byte_rates: Arc::new(Mutex::new(ByteRates::default())),
+            data_streamed: Arc::new(AtomicUsize::new(0)),
+            last_time_sample: Arc::new(AtomicUsize::new(0)),
+            stream_time: Arc::new(AtomicUsize::new(0)),
+            series: Arc::new(Mutex::new(StatsSeries::new(config::get().stats_history_size))),
+            byte_rate_limit,
+            bandwidth: Arc::new(Mutex::new(BandwidthState::default())),
+            lifecycle: Arc::new(Mutex::new(Lifecycle::new())),
+            last_webhook_at: Arc::new(AtomicU64::new(0)),
+            lag_events: Arc::new(AtomicUsize::new(0)),
+            lagged_messages: Arc::new(AtomicUsize::new(0)),
+            watcher: Arc::new(Mutex::new(WatcherHealth::new())),
+            ledger: Arc::new(Mutex::new(Ledger::default())),
+            analytics: Arc::new(Mutex::new(ViewerAnalytics::default())),
+            closed: Arc::new(watch::channel(false).0),
+            span,
+        }
+    }
+
+    /// Rebuilds a room from its persisted snapshot.
+    fn restore(snapshot: RoomSnapshot) -> Self {
+        let room = Self::new(
+            snapshot.room_id,
+            snapshot.live_server,
+            snapshot.stats_backend,
+            snapshot.multiplier_policy,
+            snapshot.byte_rate_limit,
+        );
+
+        let mut ledger = snapshot.ledger;
+        ledger.set_live(snapshot.stream.state.is_live());
+        ledger.set_multiplier(snapshot.multiplier);
+
+        Self {
+            created_at: snapshot.created_at,
+            multiplier: Arc::new(Mutex::new(snapshot.multiplier)),
+            avg_byte_rate: Arc::new(AtomicUsize::new(snapshot.avg_byte_rate)),
+            data_streamed: Arc::new(AtomicUsize::new(snapshot.data_streamed)),
+            last_time_sample: Arc::new(AtomicUsize::new(snapshot.last_time_sample)),
+            stream_time: Arc::new(AtomicUsize::new(snapshot.stream_time)),
+            bandwidth: Arc::new(Mutex::new(snapshot.bandwidth)),
+            ledger: Arc::new(Mutex::new(ledger)),
+            analytics: Arc::new(Mutex::new(snapshot.viewers)),
+            lifecycle: Arc::new(Mutex::new(snapshot.stream)),
+            ..room
+        }
+    }
+
+    /// The room's definition and accumulated stats to be persisted.
+    fn snapshot(&self) -> RoomSnapshot {
+        RoomSnapshot {
+            room_id: self.room_id.to_string(),
+            live_server: self.live_server.to_string(),
+            stats_backend: self.stats_backend,
+            created_at: self.created_at,
+            multiplier: *self.multiplier.lock().unwrap(),
+            multiplier_policy: self.multiplier_kind.clone(),
+            avg_byte_rate: self.avg_byte_rate.load(Relaxed),
+            data_streamed: self.data_streamed.load(Relaxed),
+            last_time_sample: self.last_time_sample.load(Relaxed),
+            stream_time: self.stream_time.load(Relaxed),
+            byte_rate_limit: self.byte_rate_limit,
+            bandwidth: self.bandwidth.lock().unwrap().clone(),
+            stream: self.lifecycle.lock().unwrap().clone(),
+            ledger: self.ledger.lock().unwrap().clone(),
+            viewers: self.analytics.lock().unwrap().clone(),
+        }
+    }
+
+    /// Every user's XP in the room.
+    pub fn xp_ledger(&self) -> Vec<LedgerEntry> {
+        self.ledger.lock().unwrap().entries()
+    }
+
+    /// If any of the room's locks were poisoned by a panic.
+    fn is_poisoned(&self) -> bool {
+        self.presence.is_poisoned()
+            | self.history.is_poisoned()
+            | self.sessions.is_poisoned()
+            | self.byte_rates.is_poisoned()
+            | self.lifecycle.is_poisoned()
+            | self.watcher.is_poisoned()
+            | self.ledger.is_poisoned()
+            | self.multiplier.is_poisoned()
+            | self.bandwidth.is_poisoned()
+            | self.series.is_poisoned()
+            | self.analytics.is_poisoned()
+    }
+
+    /// Spawns the room's stats watcher in the room's span.
+    fn spawn_watcher(&self, shutdown: watch::Receiver<Phase>) -> JoinHandle<()> {
+        let span = self.span.clone();
+        tokio::spawn(self.clone().watch_stats(shutdown).instrument(span))
+    }
+
+    /// Gives an event the next sequence number, stores it in the room's
+    /// history and sends it to the broadcast channel.
+    pub fn emit(&self, event: GatewayEvent) {
+        // The lock is held while sending so the channel sees events in
+        // sequence order.
+        let mut history = self.history.lock().unwrap();
+        let frame = history.push(|seq| event.to_sequenced_frame(seq));
+        let _ = self.sender.send(frame);
+
+        metrics::MESSAGES_BROADCAST
+            .with_label_values(&[&event.opcode().to_string()])
+            .inc();
+    }
+
+    /// Sends a frame to the room exactly as it was given, for the
+    /// deprecated emit route. It is stored in the room's history so it is
+    /// replayed to resuming clients, but it does not carry its sequence
+    /// number.
+    pub fn emit_raw(&self, frame: String) {
+        let mut history = self.history.lock().unwrap();
+        let frame = history.push(|_| frame);
+        let _ = self.sender.send(frame);
+
+        metrics::MESSAGES_BROADCAST
+            .with_label_values(&["raw"])
+            .inc();
+    }
+
+    /// Counts a connection as joined to the room until the returned guard
+    /// is dropped.
+    pub fn track_connection(&self) -> ConnectionGuard {
+        self.sockets.fetch_add(1, Relaxed);
+        ConnectionGuard {
+            connections: self.sockets.clone(),
+        }
+    }
+
+    /// Where the room's stream can be watched.
+    fn live_ready(&self, lifecycle: &Lifecycle) -> LiveReady {
+        LiveReady {
+            stream_url: format!("{}/live/{}.m3u8", &self.live_server, &self.room_id),
+            started_at: lifecycle.started_at.unwrap_or(0),
+            live_at: lifecycle.changed_at,
+        }
+    }
+
+    /// The event announcing the stream's current state, None if there is
+    /// no stream published.
+    fn lifecycle_event(&self, lifecycle: &Lifecycle) -> Option<GatewayEvent> {
+        let started_at = lifecycle.started_at.unwrap_or(0);
+
+        let event = match lifecycle.state {
+            StreamState::Idle => return None,
+            StreamState::Starting => GatewayEvent::LiveStarting(LiveStarting {
+                started_at,
+            }),
+            StreamState::Live => GatewayEvent::LiveReady(self.live_ready(lifecycle)),
+            StreamState::Stalled => GatewayEvent::LiveStalled(LiveStalled {
+                started_at,
+                stalled_at: lifecycle.changed_at,
+            }),
+            StreamState::Ended => GatewayEvent::LiveEnded(LiveEnded {
+                reason: lifecycle.end_reason.clone().unwrap_or_else(|| "unknown".to_string()),
+                started_at: lifecycle.started_at,
+                ended_at: lifecycle.ended_at.unwrap_or(lifecycle.changed_at),
+                viewers: self.analytics
+                    .lock()
+                    .unwrap()
+                    .last_stream()
+                    .map(|summary| summary.viewers),
+            }),
+        };
+
+        Some(event)
+    }
+
+    /// The event telling a joining client about the room's stream, None
+    /// if there is no stream published.
+    pub fn stream_state_event(&self) -> Option<GatewayEvent> {
+        let lifecycle = self.lifecycle.lock().unwrap();
+        if lifecycle.state == StreamState::Ended {
+            return None
+        }
+
+        self.lifecycle_event(&lifecycle)
+    }
+
+    /// Hands stats pushed to the gateway to the room's stats source,
+    /// returning false if the source does not take pushed stats.
+    pub fn push_stats(&self, status: StreamStatus) -> bool {
+        self.stats_source.push(status)
+    }
+
+    /// Applies a change to the stream's lifecycle, broadcasting exactly
+    /// one event if its state changed.
+    ///
+    /// The lifecycle lock is held while emitting so clients see the state
+    /// changes in the order they happened.
+    fn transition(&self, change: impl FnOnce(&mut Lifecycle) -> bool) {
+        let mut lifecycle = self.lifecycle.lock().unwrap();
+        if !change(&mut lifecycle) {
+            return
+        }
+
+        self.span.in_scope(|| tracing::info!(state = ?lifecycle.state, "Stream changed state"));
+        self.ledger.lock().unwrap().set_live(lifecycle.state.is_live());
+
+        match lifecycle.state {
+            StreamState::Starting => {
+                self.byte_rates.lock().unwrap().clear();
+                self.bandwidth.lock().unwrap().reset();
+                self.viewers_started();
+            },
+            StreamState::Ended => self.viewers_finished(&lifecycle),
+            _ => {},
+        }
+
+        if let Some(event) = self.lifecycle_event(&lifecycle) {
+            self.emit(event);
+        }
+    }
+
+    /// Starts counting the viewers of a stream that was just published.
+    fn viewers_started(&self) {
+        let present = self.presence
+            .lock()
+            .unwrap()
+            .users()
+            .into_iter()
+            .map(|user| user.id)
+            .collect();
+
+        self.analytics.lock().unwrap().start(present);
+    }
+
+    /// Stops counting the viewers of a stream that just ended and logs
+    /// its summary.
+    fn viewers_finished(&self, lifecycle: &Lifecycle) {
+        let summary = self.analytics.lock().unwrap().finish(lifecycle.end_reason.clone());
+        if let Some(summary) = summary {
+            let viewers = &summary.viewers;
+            self.span.in_scope(|| tracing::info!(
+                peak_viewers = viewers.peak_viewers,
+                unique_viewers = viewers.unique_viewers,
+                joins = viewers.joins,
+                leaves = viewers.leaves,
+                total_watch_time = viewers.total_watch_time,
+                average_watch_time = viewers.average_watch_time,
+                "Stream ended, Peak: {}, Unique: {}, Avg Watch Time: {}",
+                viewers.peak_viewers,
+                viewers.unique_viewers,
+                utils::humanize(Duration::from_millis(viewers.average_watch_time)),
+            ));
+        }
+    }
+
+    /// Records the stream being published.
+    fn stream_started(&self) {
+        self.transition(|lifecycle| lifecycle.published());
+    }
+
+    /// Records a sample of the stream, a stream that was not already
+    /// published is started first.
+    fn stream_sampled(&self, sample: &StatsSample) {
+        self.stream_started();
+        self.transition(|lifecycle| lifecycle.sampled(sample.bytes_per_sec > 0));
+    }
+
+    /// Records the stream ending.
+    fn stream_ended(&self, reason: &'static str) {
+        self.last_time_sample.store(0, Relaxed);
+        self.transition(|lifecycle| lifecycle.ended(reason));
+    }
+
+    /// Marks the room as not live because its stats can no longer be
+    /// fetched, unless the webhooks are still saying otherwise.
+    fn stats_unavailable(&self) {
+        if !self.webhooks_active() {
+            self.stream_ended("stats_unavailable");
+        }
+    }
+
+    /// Called by the live server's `on_publish` webhook when it starts
+    /// receiving the room's stream.
+    pub fn stream_published(&self) {
+        self.webhook_received();
+        self.stream_started();
+    }
+
+    /// Called by the live server's `on_unpublish` webhook when the room's
+    /// stream stops.
+    pub fn stream_unpublished(&self) {
+        self.webhook_received();
+        self.push_stats(StreamStatus::NotLive);
+        self.stream_ended("unpublished");
+    }
+
+    /// Called by the live server's `on_stats` webhook with the stream's
+    /// current stats.
+    pub fn stream_stats(&self, sample: StatsSample) {
+        self.webhook_received();
+        self.push_stats(StreamStatus::Live(sample.clone()));
+        self.stream_sampled(&sample);
+        self.record_sample(&sample);
+    }
+
+    /// Records that the live server just called a webhook for the room.
+    fn webhook_received(&self) {
+        self.last_webhook_at.store(utils::now_millis(), Relaxed);
+    }
+
+    /// If the live server has called a webhook for the room within the
+    /// last `WEBHOOK_TIMEOUT` seconds, while it has the webhooks decide
+    /// if the room is live rather than the stats watcher.
+    fn webhooks_active(&self) -> bool {
+        let last = self.last_webhook_at.load(Relaxed);
+        let timeout = config::get().webhook_timeout * 1000;
+
+        (last > 0) & (utils::now_millis().saturating_sub(last) < timeout)
+    }
+
+    /// Records the room's watcher exiting and schedules its restart.
+    ///
+    /// A watcher that gave up has already marked the room as not live,
+    /// a panicked one could not so it is done here.
+    fn watcher_exited(&self, panicked: bool) {
+        let exit = if panicked {
+            self.stats_unavailable();
+            WatcherExit::Panicked
+        } else {
+            WatcherExit::GaveUp
+        };
+
+        let delay = self.watcher.lock().unwrap().exited(exit);
+        self.span.in_scope(|| tracing::warn!(
+            ?exit,
+            restart_in = %utils::humanize(delay),
+            "Stats watcher exited",
+        ));
+    }
+
+    /// Records that a client fell too far behind the broadcast channel and
+    /// missed `skipped` events.
+    pub fn record_lag(&self, skipped: u64) {
+        self.lag_events.fetch_add(1, Relaxed);
+        self.lagged_messages.fetch_add(skipped as usize, Relaxed);
+
+        metrics::LAG_EVENTS.inc();
+        metrics::LAGGED_MESSAGES.inc_by(skipped);
+    }
+
+    /// Gives a lagging client a fresh subscription at the head of the
+    /// broadcast channel along with a snapshot of the room's current state.
+    ///
+    /// The subscription is made while the history is locked so the
+    /// snapshot's `seq` is exactly the last event the client will not see.
+    pub fn resync(&self, skipped: u64) -> (RoomReceiver, GatewayEvent) {
+        // Taken before the history lock as `transition` does.
+        let lifecycle = self.lifecycle.lock().unwrap();
+        let history = self.history.lock().unwrap();
+
+        let live = if lifecycle.state.is_live() {
+            Some(self.live_ready(&lifecycle))
+        } else {
+            None
+        };
+
+        let event = GatewayEvent::Resync(Resync {
+            seq: history.last_seq(),
+            skipped,
+            stats: self.get_basic_stats(),
+            members: self.presence.lock().unwrap().users(),
+            live,
+            stream: lifecycle.clone(),
+        });
+
+        (self.sender.subscribe(), event)
+    }
+
+    /// Closes every connection to the room as it has been deleted.
+    fn close(&self) {
+        self.closed.send_replace(true);
+    }
+
+    /// Watches for the room being deleted.
+    pub fn closed(&self) -> watch::Receiver<bool> {
+        self.closed.subscribe()
     }
 
     /// Subscribes to the broadcasting channel/
@@ -234,288 +1339,554 @@ impl Room {
         self.sender.subscribe()
     }
 
+    /// Registers a new session that can later be resumed.
+    pub fn open_session(&self, session_id: String, connection_id: u64, user_id: String) {
+        self.sessions
+            .lock()
+            .unwrap()
+            .open(session_id, connection_id, user_id);
+    }
+
+    /// Takes back a suspended session, returning its connection id, a
+    /// subscription to the room and every event after `seq` it missed.
+    ///
+    /// The subscription is made while the history is locked so no event
+    /// is both replayed and received. Returns None if the session can not
+    /// be resumed or the missed events are no longer buffered.
+    pub fn resume_session(
+        &self,
+        session_id: &str,
+        user_id: &str,
+        seq: u64,
+    ) -> Option<(u64, RoomReceiver, Vec<Arc<RoomEvent>>)> {
+        let mut sessions = self.sessions.lock().unwrap();
+        let history = self.history.lock().unwrap();
+
+        let missed = history.since(seq)?;
+        let connection_id = sessions.resume(session_id, user_id)?;
+
+        Some((connection_id, self.sender.subscribe(), missed))
+    }
+
+    /// Marks a session's connection as dropped, if it is not resumed
+    /// within `RESUME_GRACE` seconds the user leaves the room.
+    pub fn suspend_session(&self, session_id: String) {
+        self.sessions.lock().unwrap().suspend(&session_id);
+
+        let room = self.clone();
+        tokio::spawn(async move {
+            let grace = Duration::from_secs(config::get().resume_grace);
+            time::sleep(grace).await;
+
+            let expired = room.sessions.lock().unwrap().expire(&session_id, grace);
+            if let Some(connection_id) = expired {
+                room.member_leave(connection_id);
+            }
+        });
+    }
+
+    /// Ends a session for good, the user leaves the room straight away.
+    pub fn close_session(&self, session_id: &str) {
+        let closed = self.sessions.lock().unwrap().close(session_id);
+        if let Some(connection_id) = closed {
+            self.member_leave(connection_id);
+        }
+    }
+
     /// The amount of members in the room
     pub fn member_count(&self) -> usize {
         self.members.load(Relaxed)
     }
 
-    /// Increments the counter on members atomically by 1 and then sends
-    /// the stats to all members in the room.
-    pub fn member_join(&self) {
-        let old = self.members.fetch_add(1, Relaxed);
-
-        let multiplier_maybe = ((old + 1) as f32).log10() * 4f32;
-        self.adjust_modifier(multiplier_maybe.round() as usize);
-
-        let stats = self.get_basic_stats();
+    /// Adds a connection to the room's presence.
+    ///
+    /// If this is the user's first connection the counter on members is
+    /// incremented by 1 and a presence join and the new stats are sent to
+    /// all members in the room, extra tabs from the same user are not counted.
+    pub fn member_join(&self, connection_id: u64, user: UserProfile) {
+        let members = {
+            let mut presence = self.presence.lock().unwrap();
+            if !presence.join(connection_id, user.clone()) {
+                return
+            }
 
-        // This will never error, i think.
-        let val = serde_json::to_value(stats).unwrap();
-        let wrapped = WsMessage {
-            opcode: opcodes::OP_STATS_UPDATE,
-            payload: Some(val),
+            let members = presence.user_count();
+            self.members.store(members, Relaxed);
+            members
         };
 
-        let msg = serde_json::to_string(&wrapped).unwrap();
+        self.ledger.lock().unwrap().join(&user.id);
+        self.analytics.lock().unwrap().join(&user.id);
+        self.emit(GatewayEvent::PresenceJoin(user));
 
-        self.send(msg);
+        self.update_multiplier(members);
     }
 
 
-    /// Lowers the counter on members atomically and then sends the updated
-    /// stats to all other members in the room.
-    pub fn member_leave(&self) {
-        let old = self.members.fetch_sub(1, Relaxed);
+    /// Removes a connection from the room's presence.
+    ///
+    /// If this was the user's last connection the counter on members is
+    /// lowered and a presence leave and the updated stats are sent to all
+    /// other members in the room.
+    pub fn member_leave(&self, connection_id: u64) {
+        let (user, members) = {
+            let mut presence = self.presence.lock().unwrap();
+            let user = match presence.leave(connection_id) {
+                Some(user) => user,
+                None => return,
+            };
 
-        let multiplier_maybe = ((old - 1) as f32).log10() * 4f32;
-        self.adjust_modifier(multiplier_maybe.round() as usize);
+            let members = presence.user_count();
+            self.members.store(members, Relaxed);
+            (user, members)
+        };
 
-        let stats = self.get_basic_stats();
+        self.ledger.lock().unwrap().leave(&user.id);
+        self.analytics.lock().unwrap().leave(&user.id);
+        self.emit(GatewayEvent::PresenceLeave(user));
 
-        // This will never error, i think.
-        let val = serde_json::to_value(stats).unwrap();
-        let wrapped = WsMessage {
-            opcode: opcodes::OP_STATS_UPDATE,
-            payload: Some(val),
-        };
+        self.update_multiplier(members);
+    }
 
-        let msg = serde_json::to_string(&wrapped).unwrap();
+    /// The event giving a client every user currently in the room.
+    pub fn presence_snapshot_event(&self) -> GatewayEvent {
+        GatewayEvent::PresenceSnapshot(PresenceSnapshot {
+            members: self.presence.lock().unwrap().users(),
+        })
+    }
 
-        self.send(msg);
+    /// Works the multiplier out again with the room's policy for the
+    /// given amount of members and sends the new stats to the room.
+    fn update_multiplier(&self, members: usize) {
+        let multiplier = self.policy_multiplier(members);
+        self.set_multiplier(multiplier);
     }
 
-    /// Changes the multiplier, uses a percentile to represent the floating
-    /// value.
-    ///
-    /// 10 -> 1x
-    /// 15 -> 1.5x
-    pub fn adjust_modifier(&self, modifier: usize) {
-        self.multiplier.store(10 + modifier, Relaxed);
-
-        let stats = self.get_basic_stats();
-
-        // This will never error, i think.
-        let val = serde_json::to_value(stats).unwrap();
-        let wrapped = WsMessage {
-            opcode: opcodes::OP_STATS_UPDATE,
-            payload: Some(val),
-        };
+    /// Works the multiplier out again for policies that change with the
+    /// time, only sending the new stats to the room if it changed.
+    fn refresh_multiplier(&self) {
+        let multiplier = self.policy_multiplier(self.member_count());
+        if multiplier != *self.multiplier.lock().unwrap() {
+            self.set_multiplier(multiplier);
+        }
+    }
+
+    /// The multiplier the room's policy gives for the amount of members.
+    fn policy_multiplier(&self, members: usize) -> f64 {
+        self.multiplier_policy.multiplier(&MultiplierContext {
+            members,
+            now: utils::now_millis(),
+        })
+    }
 
-        let msg = serde_json::to_string(&wrapped).unwrap();
+    /// Changes the room's multiplier and sends the new stats to the room.
+    fn set_multiplier(&self, multiplier: f64) {
+        *self.multiplier.lock().unwrap() = multiplier;
+        self.ledger.lock().unwrap().set_multiplier(multiplier);
 
-        self.send(msg);
+        self.emit(GatewayEvent::StatsUpdate(self.get_basic_stats()));
+    }
+
+    /// The room's multiplier, 0 while nobody is in the room.
+    fn current_multiplier(&self) -> f64 {
+        if self.member_count() > 0 {
+            *self.multiplier.lock().unwrap()
+        } else {
+            0.0
+        }
     }
 
     /// Get the room statistics.
     ///
-    /// Loads the multiplier and calculated the floating point version of the
-    /// modifier, this is then wrapped with member count and constructed into
-    /// a Stats struct.
+    /// Loads the multiplier and formats it, this is then wrapped with
+    /// member count and constructed into a Stats struct.
     pub fn get_basic_stats(&self) -> BasicStats {
         let members = self.member_count();
-        let multiplier = if members > 0 {
-            self.multiplier.load(Relaxed) as f32 / 10f32
-        } else {
-            0f32
-        };
+        let multiplier = self.current_multiplier();
         BasicStats {
+            members,
+            multiplier: format_multiplier(multiplier),
+        }
+    }
+
+    /// Gets the overview of the room used when listing rooms.
+    pub fn get_summary(&self, watcher_running: bool) -> RoomSummary {
+        let members = self.member_count();
+        let multiplier = self.current_multiplier();
+        let stream = self.lifecycle.lock().unwrap().clone();
+
+        RoomSummary {
+            room_id: self.room_id.to_string(),
+            live_server: self.live_server.to_string(),
+            is_live: stream.state.is_live(),
+            state: stream.state,
+            members,
+            multiplier,
+            created_at: self.created_at,
+            stats_backend: self.stats_backend,
+            multiplier_policy: self.multiplier_kind.clone(),
+            byte_rate_limit: self.byte_rate_limit(),
+            watcher_running,
+        }
+    }
+
+    /// Gets the room's values exported as metrics.
+    pub fn get_metrics(&self) -> RoomMetrics {
+        RoomMetrics {
+            room_id: self.room_id.to_string(),
+            is_live: self.lifecycle.lock().unwrap().state.is_live(),
+            sockets: self.sockets.load(Relaxed),
             members: self.member_count(),
-            multiplier: format!("{:.1}x", multiplier),
+            avg_byte_rate: self.avg_byte_rate.load(Relaxed),
+            data_streamed: self.data_streamed.load(Relaxed),
+            stream_time: self.stream_time.load(Relaxed),
         }
     }
 
     /// Loads and exports the current room stats including the streaming stats.
     pub fn get_full_stats(&self) -> FullStats {
         let members = self.member_count();
-        let multiplier = if members > 0 {
-            self.multiplier.load(Relaxed) as f32 / 10f32
-        } else {
-            0f32
-        };
+        let multiplier = self.current_multiplier();
         let total_bytes_streamed = self.data_streamed.load(Relaxed);
         let avg_bytes_per_sec = self.avg_byte_rate.load(Relaxed);
-        let avg_stream_time = total_bytes_streamed / avg_bytes_per_sec;
+        let avg_stream_time = total_bytes_streamed
+            .checked_div(avg_bytes_per_sec)
+            .unwrap_or(0);
+        let presence = self.presence.lock().unwrap().users();
+        let (viewers, last_stream) = {
+            let analytics = self.analytics.lock().unwrap();
+            (analytics.stats(), analytics.last_stream())
+        };
 
         FullStats {
             members,
             multiplier,
             total_bytes_streamed,
             avg_bytes_per_sec,
-            avg_stream_time
+            avg_stream_time,
+            presence,
+            lag_events: self.lag_events.load(Relaxed),
+            lagged_messages: self.lagged_messages.load(Relaxed),
+            watcher: self.watcher.lock().unwrap().clone(),
+            stream: self.lifecycle.lock().unwrap().clone(),
+            byte_rate_limit: self.byte_rate_limit(),
+            bandwidth: self.bandwidth.lock().unwrap().clone(),
+            viewers,
+            last_stream,
         }
     }
 
+    /// The room's stats samples in the query's range.
+    pub fn get_stats_history(&self, query: &HistoryQuery) -> StatsHistory {
+        StatsHistory {
+            room_id: self.room_id.to_string(),
+            resolution: query.resolution.filter(|resolution| *resolution > 0),
+            points: self.series.lock().unwrap().query(query),
+        }
+    }
+
+    /// The most bytes per second the room's stream may average over its
+    /// last few samples, None if it is not limited.
+    fn byte_rate_limit(&self) -> Option<usize> {
+        let limit = self.byte_rate_limit.unwrap_or(config::get().room_byte_rate_limit);
+        if limit > 0 {
+            Some(limit)
+        } else {
+            None
+        }
+    }
+
+    /// Checks a rate against one of the stream's limits and acts on it if
+    /// it is over, for the global limit this is every live stream's
+    /// combined rate.
+    fn check_bandwidth(&self, scope: LimitScope, byte_rate: usize, limit: usize) {
+        let violation = self.bandwidth.lock().unwrap().check(scope, byte_rate, limit);
+        if let Some(violation) = violation {
+            self.limit_exceeded(violation);
+        }
+    }
+
+    /// Warns everyone in the room that the stream is over a limit, asks
+    /// the live server to throttle or kick the publisher and records the
+    /// violation in the room's stats once it has answered.
+    fn limit_exceeded(&self, mut violation: Violation) {
+        self.span.in_scope(|| tracing::warn!(
+            scope = violation.scope.as_str(),
+            byte_rate = violation.byte_rate,
+            limit = violation.limit,
+            action = ?violation.action,
+            "Stream is over its bandwidth limit, Rate: {}/Sec, Limit: {}/Sec",
+            utils::format_data(violation.byte_rate as f64),
+            utils::format_data(violation.limit as f64),
+        ));
+
+        metrics::BANDWIDTH_VIOLATIONS
+            .with_label_values(&[violation.scope.as_str()])
+            .inc();
+
+        self.emit(GatewayEvent::BandwidthWarning(BandwidthWarning {
+            scope: violation.scope,
+            byte_rate: violation.byte_rate,
+            limit: violation.limit,
+            action: violation.action,
+        }));
+
+        let room = self.clone();
+        let span = self.span.clone();
+        tokio::spawn(async move {
+            let result = bandwidth::enforce(&room.live_server, &room.room_id, &violation).await;
+            if let Err(e) = result {
+                tracing::error!(error = %e, "Failed to ask the live server to limit the stream");
+                violation.error = Some(e);
+            }
+
+            room.bandwidth.lock().unwrap().record(violation);
+        }.instrument(span));
+    }
+
     /// Watches the streaming server for stats and calculates the XP every
     /// minute, this is also used to work out the avg bitrate of the stream
     /// to apply a soft limit of N bytes per second as to not leave the servers
-    /// munching a insane amount of bandwidth from one server.
-    async fn watch_stats(self) {
-        let client = reqwest::Client::new();
-
+    /// munching a insane amount of bandwidth from one server, see
+    /// `record_sample`.
+    ///
+    /// While the live server is calling the room's webhooks they decide
+    /// if the room is live and record its stats, the polled stats are
+    /// ignored so no sample is counted twice.
+    ///
+    /// The watcher stops at its next sleep once the gateway starts
+    /// shutting down, a poll it is in the middle of is let finish.
+    async fn watch_stats(self, mut shutdown: watch::Receiver<Phase>) {
+        let config = config::get();
         let mut errors = 0usize;
-        let mut rates = Vec::new();
 
         loop {
-            let maybe_resp = client
-                .get(&format!(
-                    "{}/stats/livestat?room={}&authorization={}",
-                    &self.live_server,
-                    &self.room_id,
-                    API_KEY.as_str(),
-                ))
-                .send()
-                .await;
-
-            let resp = match maybe_resp {
-                Ok(resp) => {
-                    resp
+            if *shutdown.borrow() != Phase::Running {
+                return
+            }
+
+            let polled_at = Instant::now();
+            let result = self.stats_source.poll().await;
+            metrics::POLL_DURATION
+                .with_label_values(&[self.stats_backend.as_str()])
+                .observe(polled_at.elapsed().as_secs_f64());
+
+            let delay = match result {
+                Ok(StreamStatus::NotLive) => {
+                    tracing::debug!("Room is not streaming, skipping sampling");
+
+                    if !self.webhooks_active() {
+                        self.stream_ended("offline");
+                    }
+
+                    config.idle_poll_interval
+                },
+                Ok(StreamStatus::Live(sample)) => {
+                    if !self.webhooks_active() {
+                        self.stream_sampled(&sample);
+                        self.record_sample(&sample);
+                    }
+
+                    config.live_poll_interval
                 },
                 Err(e) => {
-                    eprintln!(
-                        "[ ROOM {} ] Error getting live stats: {:?}",
-                        &self.room_id,
-                        e
+                    tracing::warn!(
+                        error.kind = e.kind(),
+                        error.status = e.status(),
+                        error.detail = e.detail(),
+                        fatal = e.is_fatal(),
+                        "Failed to poll stream stats",
                     );
 
-                    errors += 1;
+                    metrics::POLL_ERRORS
+                        .with_label_values(&[self.stats_backend.as_str(), &e.status_label()])
+                        .inc();
+
+                    if e.is_fatal() {
+                        errors += 1;
+                    }
 
-                    if errors >= 3 {
-                        eprintln!(
-                            "[ ROOM {} ] Exiting stats watcher due to error overflow.",
-                            &self.room_id
-                        );
+                    if errors >= config.max_poll_errors {
+                        tracing::error!(errors, "Exiting stats watcher after too many errors");
+                        self.stats_unavailable();
                         return
                     }
-                    time::sleep(Duration::from_secs(60)).await;
 
-                    continue
-                }
+                    config.live_poll_interval
+                },
             };
 
+            tokio::select! {
+                _ = time::sleep(Duration::from_secs(delay)) => {},
+                _ = shutdown.wait_for(|phase| *phase != Phase::Running) => return,
+            }
+        }
+    }
 
-            let status = resp.status();
-
-            let msg = resp
-                    .text()
-                    .await
-                    .unwrap_or_else(|_| "".to_string());
-            let maybe_data = serde_json::from_str::<'_, StreamStatsResponse>(&msg);
-
-            if status == StatusCode::NOT_FOUND {
-                println!(
-                    "[ ROOM {} ] Room is not streaming... Aborting sampling.",
-                    &self.room_id,
-                );
+    /// Updates the room's streaming stats from a new sample.
+    ///
+    /// The average rate is the mean of the middle half of every rate seen
+    /// as to not be affected by random stops / peaks of data. The room's
+    /// bandwidth limit is checked against the mean of the last few rates
+    /// instead so a stream going over it is caught straight away. The
+    /// sample is also added to the room's stats history.
+    fn record_sample(&self, sample: &StatsSample) {
+        let total_b = sample.total_bytes;
+        self.data_streamed.store(total_b, Relaxed);
+
+        let avg_rate = self.byte_rates.lock().unwrap().push(sample.bytes_per_sec);
+        self.avg_byte_rate.store(avg_rate, Relaxed);
+
+        let last_sample = self.last_time_sample.load(Relaxed);
+        let avg_time = if (total_b > 0) & (avg_rate > 0) {
+            total_b / avg_rate
+        } else {
+            0usize
+        };
+        self.last_time_sample.store(avg_time, Relaxed);
 
-                self.is_live.store(false, Relaxed);
-                self.last_time_sample.store(0, Relaxed);
+        let mut delta = avg_time as isize - last_sample as isize;
 
-                time::sleep(Duration::from_secs(10)).await;
-                continue
-            } else if status != StatusCode::OK {
-                let msg = match maybe_data {
-                    // Api responded with a custom error detail.
-                    Ok(error_resp) => {
-                        format!("{:?}", error_resp.data)
-                    },
-
-                    // The api crashed un-expectantly and was unable to produce
-                    // a usable detail.
-                    Err(_) => {
-                        format!("Error has no details: {}", &msg)
-                    }
-                };
+        if delta < 0 {
+            delta = 0;
+        }
 
-                eprintln!(
-                    "[ ROOM {} ] Unexpected api response with status: {}, Msg: {}",
-                    &self.room_id,
-                    status.as_str(),
-                    msg,
-                );
+        let old = self.stream_time.fetch_add(delta as usize, Relaxed);
 
-                time::sleep(Duration::from_secs(60)).await;
-                continue
-            } else {
-                self.is_live.store(true, Relaxed);
+        let point = StatsPoint {
+            at: utils::now_millis(),
+            bytes_per_sec: sample.bytes_per_sec,
+            avg_bytes_per_sec: avg_rate,
+            total_bytes: total_b,
+            members: self.member_count(),
+            multiplier: self.current_multiplier(),
+        };
+        self.series.lock().unwrap().push(point);
+
+        self.span.in_scope(|| tracing::info!(
+            total_bytes = total_b,
+            avg_bytes_per_sec = avg_rate,
+            stream_time = old + delta as usize,
+            "Sampled stream, Total: {}, Avg Rate: {}/Sec, Avg Time: {}",
+            utils::format_data(total_b as f64),
+            utils::format_data(avg_rate as f64),
+            utils::humanize(Duration::from_secs(
+                (old + delta as usize) as u64
+            )),
+        ));
+
+        let rate = self.bandwidth.lock().unwrap().sample(sample.bytes_per_sec);
+        if let Some(limit) = self.byte_rate_limit() {
+            self.check_bandwidth(LimitScope::Room, rate, limit);
+        }
+    }
+}
 
-                let url = format!("{}/live/{}.m3u8", &self.live_server, &self.room_id);
-                let payload = serde_json::json!({
-                    "opcode":  opcodes::OP_LIVE_READY,
-                    "payload": {
-                        "stream_url": url,
-                    }
-                });
-                self.send(serde_json::to_string(&payload).unwrap())
-            }
 
-            let data = maybe_data.unwrap();
-            let stats = serde_json::from_value::<StreamStats>(data.data);
-            match stats {
-                Ok(stats) => {
-                    let total_b = stats.video_total_bytes + stats.audio_total_bytes;
-                    self.data_streamed.store(total_b, Relaxed);
-
-                    rates.push(((stats.video_speed + stats.audio_speed) * 1000) / 8);
-                    rates.sort();
-
-                    let mid = rates.len() / 2;
-                    let side_split = mid / 2;
-                    let sliced = &rates[side_split..rates.len() - side_split];
-                    let avg_rate = sliced.iter().sum::<usize>() / sliced.len();
-                    self.avg_byte_rate.store(avg_rate, Relaxed);
-
-                    let last_sample = self.last_time_sample.load(Relaxed);
-                    let avg_time = if (total_b > 0) & (avg_rate > 0) {
-                        total_b / avg_rate
-                    } else {
-                        0usize
-                    };
-                    self.last_time_sample.store(avg_time, Relaxed);
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::NoStore;
+
+    /// Adds a live room whose stream is running at `rate`, giving a
+    /// receiver for the room's events.
+    fn live_room(manager: &RoomManager, room_id: &str, rate: usize) -> RoomReceiver {
+        config::init_default();
+
+        let room = Room::new(
+            room_id.to_string(),
+            "http://live".to_string(),
+            StatsBackend::Push,
+            MultiplierKind::Log10,
+            None,
+        );
+        room.lifecycle.lock().unwrap().state = StreamState::Live;
+        room.bandwidth.lock().unwrap().sample(rate);
+
+        let receiver = room.sender.subscribe();
+        manager.rooms.insert(room_id.to_string(), room);
+        receiver
+    }
 
-                    let mut delta = avg_time as isize - last_sample as isize;
+    /// The byte rate of the bandwidth warning the room was sent, if any.
+    fn warned_rate(receiver: &mut RoomReceiver) -> Option<usize> {
+        let event = receiver.try_recv().ok()?;
+        let frame: serde_json::Value = serde_json::from_str(&event.frame).unwrap();
+        assert_eq!(frame["opcode"], 23);
+        assert_eq!(frame["payload"]["scope"], "global");
+        frame["payload"]["byte_rate"].as_u64().map(|rate| rate as usize)
+    }
 
-                    if delta < 0 {
-                        delta = 0;
-                    }
+    #[test]
+    fn average_rate_skips_the_outliers() {
+        let mut rates = ByteRates::default();
+        assert_eq!(rates.average(), 0);
+        assert_eq!(rates.push(100), 100);
 
-                    let old = self.stream_time.fetch_add(delta as usize, Relaxed);
-
-                    println!(
-                        "[ ROOM {} ] Sampled steam, Total: {:}, Avg Rate: {}/Sec, Avg Time: {}",
-                        &self.room_id,
-                        utils::format_data(total_b as f64),
-                        utils::format_data(avg_rate as f64),
-                        utils::humanize(Duration::from_secs(
-                            (old + delta as usize) as u64
-                        )),
-                    )
-                },
-                Err(e) => {
-                    eprintln!(
-                        "[ ROOM {} ] Error de-serializing stream stats: {:?}, Origin: {}",
-                        &self.room_id,
-                        e,
-                        &msg,
-                    );
+        for rate in [1, 100, 100, 100, 100, 100, 10_000] {
+            rates.push(rate);
+        }
+        assert_eq!(rates.average(), 100);
+    }
 
-                    errors += 1;
+    #[test]
+    fn average_rate_only_uses_recent_samples() {
+        let mut rates = ByteRates::default();
+        for _ in 0..AVERAGE_RATE_SAMPLES {
+            rates.push(1_000);
+        }
+        for _ in 0..AVERAGE_RATE_SAMPLES {
+            rates.push(10);
+        }
 
-                    if errors >= 3 {
-                        eprintln!(
-                            "[ ROOM {} ] Exiting stats watcher due to error overflow.",
-                            &self.room_id
-                        );
-                        return
-                    }
-                }
-            };
+        assert_eq!(rates.samples.len(), AVERAGE_RATE_SAMPLES);
+        assert_eq!(rates.sorted, vec![10; AVERAGE_RATE_SAMPLES]);
+        assert_eq!(rates.average(), 10);
 
+        rates.clear();
+        assert_eq!(rates.average(), 0);
+        assert_eq!(rates.push(50), 50);
+    }
 
-           time::sleep(Duration::from_secs(60)).await;
-        }
+    #[tokio::test]
+    async fn biggest_rooms_are_warned_until_the_rest_fit() {
+        let manager = RoomManager::new(Arc::new(NoStore));
+        let mut small = live_room(&manager, "small", 100);
+        let mut big = live_room(&manager, "big", 500);
+        let mut medium = live_room(&manager, "medium", 300);
+
+        manager.check_bandwidth(600);
+        assert_eq!(warned_rate(&mut big), Some(900));
+        assert_eq!(warned_rate(&mut medium), None);
+        assert_eq!(warned_rate(&mut small), None);
+
+        manager.check_bandwidth(200);
+        assert_eq!(warned_rate(&mut big), None);
+        assert_eq!(warned_rate(&mut medium), Some(900));
+        assert_eq!(warned_rate(&mut small), None);
     }
-}
 
+    #[tokio::test]
+    async fn rooms_under_the_global_limit_are_not_warned() {
+        let manager = RoomManager::new(Arc::new(NoStore));
+        let mut first = live_room(&manager, "first", 300);
+        let mut second = live_room(&manager, "second", 300);
 
+        manager.check_bandwidth(600);
+        manager.check_bandwidth(0);
+        assert_eq!(warned_rate(&mut first), None);
+        assert_eq!(warned_rate(&mut second), None);
+    }
 
+    #[tokio::test]
+    async fn rooms_that_are_not_live_do_not_count() {
+        let manager = RoomManager::new(Arc::new(NoStore));
+        let mut live = live_room(&manager, "live", 500);
+        let mut ended = live_room(&manager, "ended", 500);
+        manager.rooms.get("ended").unwrap().lifecycle.lock().unwrap().state = StreamState::Ended;
 
+        manager.check_bandwidth(600);
+        assert_eq!(warned_rate(&mut live), None);
+        assert_eq!(warned_rate(&mut ended), None);
+    }
+}
@@ -0,0 +1,183 @@
+use prometheus::{
+    Encoder,
+    HistogramOpts,
+    HistogramVec,
+    IntCounter,
+    IntCounterVec,
+    IntGauge,
+    IntGaugeVec,
+    Opts,
+    Registry,
+    TextEncoder,
+};
+
+use crate::managers::RoomManager;
+
+
+lazy_static! {
+    static ref REGISTRY: Registry = Registry::new();
+
+    /// The amount of rooms, set when scraped.
+    static ref ROOMS: IntGauge = register(IntGauge::new(
+        "gateway_rooms",
+        "The amount of rooms.",
+    ));
+
+    /// The amount of rooms that are live, set when scraped.
+    static ref LIVE_ROOMS: IntGauge = register(IntGauge::new(
+        "gateway_live_rooms",
+        "The amount of rooms whose stream can be watched.",
+    ));
+
+    /// The amount of open websockets, set when scraped.
+    static ref SOCKETS: IntGauge = register(IntGauge::new(
+        "gateway_sockets",
+        "The amount of open websocket connections including ones still identifying.",
+    ));
+
+    /// The amount of websockets in each room, set when scraped.
+    static ref ROOM_SOCKETS: IntGaugeVec = register(IntGaugeVec::new(
+        Opts::new("gateway_room_sockets", "The amount of joined websocket connections in a room."),
+        &["room_id"],
+    ));
+
+    /// The unique members of each room, set when scraped.
+    static ref ROOM_MEMBERS: IntGaugeVec = register(IntGaugeVec::new(
+        Opts::new("gateway_room_members", "The amount of unique users in a room."),
+        &["room_id"],
+    ));
+
+    /// The average byte rate of each room's stream, set when scraped.
+    static ref ROOM_AVG_BYTE_RATE: IntGaugeVec = register(IntGaugeVec::new(
+        Opts::new(
+            "gateway_room_avg_bytes_per_second",
+            "The average rate a room's stream is sent to the live server at.",
+        ),
+        &["room_id"],
+    ));
+
+    /// The bytes streamed in each room, set when scraped.
+    static ref ROOM_DATA_STREAMED: IntGaugeVec = register(IntGaugeVec::new(
+        Opts::new(
+            "gateway_room_streamed_bytes",
+            "The total amount of bytes streamed to the live server for a room.",
+        ),
+        &["room_id"],
+    ));
+
+    /// The approx time each room has streamed for, set when scraped.
+    static ref ROOM_STREAM_TIME: IntGaugeVec = register(IntGaugeVec::new(
+        Opts::new(
+            "gateway_room_stream_time_seconds",
+            "The approx time a room has been streaming for.",
+        ),
+        &["room_id"],
+    ));
+
+    /// Events broadcast to rooms.
+    pub static ref MESSAGES_BROADCAST: IntCounterVec = register(IntCounterVec::new(
+        Opts::new("gateway_messages_broadcast_total", "The amount of events broadcast to rooms."),
+        &["opcode"],
+    ));
+
+    /// Times a client fell behind a room's broadcast channel.
+    pub static ref LAG_EVENTS: IntCounter = register(IntCounter::new(
+        "gateway_lag_events_total",
+        "The amount of times a client fell behind a room's broadcast channel.",
+    ));
+
+    /// Events clients missed by falling behind.
+    pub static ref LAGGED_MESSAGES: IntCounter = register(IntCounter::new(
+        "gateway_lagged_messages_total",
+        "The amount of events dropped for clients that fell behind.",
+    ));
+
+    /// How long the stats watchers' polls take.
+    pub static ref POLL_DURATION: HistogramVec = register(HistogramVec::new(
+        HistogramOpts::new(
+            "gateway_stats_poll_duration_seconds",
+            "How long polling a room's stats source takes.",
+        ).buckets(vec![0.01, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0]),
+        &["backend"],
+    ));
+
+    /// Failed stats watcher polls.
+    pub static ref POLL_ERRORS: IntCounterVec = register(IntCounterVec::new(
+        Opts::new(
+            "gateway_stats_poll_errors_total",
+            "The amount of failed stats polls by the http status or kind of failure.",
+        ),
+        &["backend", "status"],
+    ));
+
+    /// Times a room's stream was found over a bandwidth limit.
+    pub static ref BANDWIDTH_VIOLATIONS: IntCounterVec = register(IntCounterVec::new(
+        Opts::new(
+            "gateway_bandwidth_violations_total",
+            "The amount of times a stream was found over a bandwidth limit.",
+        ),
+        &["scope"],
+    ));
+
+    /// Websockets refused before or while joining a room.
+    pub static ref UPGRADE_FAILURES: IntCounterVec = register(IntCounterVec::new(
+        Opts::new(
+            "gateway_upgrade_failures_total",
+            "The amount of websocket connections refused before joining a room.",
+        ),
+        &["reason"],
+    ));
+}
+
+
+/// Registers a metric with the gateway's registry.
+fn register<M>(metric: prometheus::Result<M>) -> M
+where
+    M: prometheus::core::Collector + Clone + 'static,
+{
+    let metric = metric.expect("metric options are valid");
+    REGISTRY
+        .register(Box::new(metric.clone()))
+        .expect("metric names are unique");
+    metric
+}
+
+
+/// Updates the gauges read from the rooms and renders every metric in
+/// the Prometheus text format.
+pub fn render(rooms: &RoomManager) -> String {
+    ROOM_SOCKETS.reset();
+    ROOM_MEMBERS.reset();
+    ROOM_AVG_BYTE_RATE.reset();
+    ROOM_DATA_STREAMED.reset();
+    ROOM_STREAM_TIME.reset();
+
+    let mut room_count = 0;
+    let mut live_count = 0;
+
+    for room in rooms.metrics() {
+        let labels = &[room.room_id.as_str()];
+
+        room_count += 1;
+        if room.is_live {
+            live_count += 1;
+        }
+
+        ROOM_SOCKETS.with_label_values(labels).set(room.sockets as i64);
+        ROOM_MEMBERS.with_label_values(labels).set(room.members as i64);
+        ROOM_AVG_BYTE_RATE.with_label_values(labels).set(room.avg_byte_rate as i64);
+        ROOM_DATA_STREAMED.with_label_values(labels).set(room.data_streamed as i64);
+        ROOM_STREAM_TIME.with_label_values(labels).set(room.stream_time as i64);
+    }
+
+    ROOMS.set(room_count);
+    LIVE_ROOMS.set(live_count);
+    SOCKETS.set(rooms.connection_count() as i64);
+
+    let mut buffer = Vec::new();
+    TextEncoder::new()
+        .encode(&REGISTRY.gather(), &mut buffer)
+        .expect("the text encoder can always write to a vec");
+
+    String::from_utf8(buffer).expect("the text format is utf-8")
+}
@@ -0,0 +1,383 @@
+use serde::{Serialize, Deserialize};
+
+use std::sync::Arc;
+
+
+/// What a multiplier policy can base a room's multiplier on.
+#[derive(Debug, Clone)]
+pub struct MultiplierContext {
+    /// The amount of unique users in the room.
+    pub members: usize,
+
+    /// The current time as a unix timestamp in ms.
+    pub now: u64,
+}
+
+
+/// Works out a room's XP multiplier.
+///
+/// The multiplier is worked out again every time someone joins or leaves
+/// the room and every `SUPERVISOR_INTERVAL` seconds for policies that
+/// change with the time, 1.0 is no bonus.
+pub trait MultiplierPolicy: Send + Sync {
+    fn multiplier(&self, context: &MultiplierContext) -> f64;
+}
+
+
+/// The original formula, a tenth more for every quarter of an order of
+/// magnitude of members e.g. 1.0x for 1 member, 1.4x for 10, 1.8x for 100.
+pub struct Log10Policy;
+
+impl MultiplierPolicy for Log10Policy {
+    fn multiplier(&self, context: &MultiplierContext) -> f64 {
+        let steps = ((context.members as f64).log10() * 4.0).round().max(0.0);
+        1.0 + steps / 10.0
+    }
+}
+
+
+/// The original formula but never above `max`.
+pub struct CappedPolicy {
+    max: f64,
+}
+
+impl MultiplierPolicy for CappedPolicy {
+    fn multiplier(&self, context: &MultiplierContext) -> f64 {
+        Log10Policy.multiplier(context).min(self.max)
+    }
+}
+
+
+/// A table of member counts and the multiplier a room gets once it has
+/// at least that many members.
+pub struct StepsPolicy {
+    steps: Vec<Step>,
+}
+
+impl MultiplierPolicy for StepsPolicy {
+    fn multiplier(&self, context: &MultiplierContext) -> f64 {
+        self.steps
+            .iter()
+            .rev()
+            .find(|step| context.members >= step.members)
+            .map(|step| step.multiplier)
+            .unwrap_or(1.0)
+    }
+}
+
+
+/// The same multiplier no matter who is in the room.
+pub struct FixedPolicy {
+    value: f64,
+}
+
+impl MultiplierPolicy for FixedPolicy {
+    fn multiplier(&self, _context: &MultiplierContext) -> f64 {
+        self.value
+    }
+}
+
+
+/// The original formula multiplied by a boost for the time of day, e.g.
+/// double XP during an evening event.
+///
+/// The first boost covering the current hour in UTC is used, outside of
+/// every boost there is no extra.
+pub struct TimeOfDayPolicy {
+    boosts: Vec<Boost>,
+}
+
+impl MultiplierPolicy for TimeOfDayPolicy {
+    fn multiplier(&self, context: &MultiplierContext) -> f64 {
+        let hour = ((context.now / 3_600_000) % 24) as u32;
+        let boost = self.boosts
+            .iter()
+            .find(|boost| boost.covers(hour))
+            .map(|boost| boost.multiplier)
+            .unwrap_or(1.0);
+
+        Log10Policy.multiplier(context) * boost
+    }
+}
+
+
+/// The original formula plus a bonus the room's host chose when making
+/// the room.
+pub struct HostBonusPolicy {
+    bonus: f64,
+}
+
+impl MultiplierPolicy for HostBonusPolicy {
+    fn multiplier(&self, context: &MultiplierContext) -> f64 {
+        Log10Policy.multiplier(context) + self.bonus
+    }
+}
+
+
+/// A row of the steps policy's table.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Step {
+    pub members: usize,
+    pub multiplier: f64,
+}
+
+
+/// A time of day the time of day policy boosts the multiplier at.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Boost {
+    /// The hour in UTC the boost starts at.
+    pub from_hour: u32,
+
+    /// The hour in UTC the boost stops at, if this is before `from_hour`
+    /// the boost runs past midnight.
+    pub to_hour: u32,
+
+    pub multiplier: f64,
+}
+
+impl Boost {
+    /// If the boost is running during the given hour in UTC.
+    fn covers(&self, hour: u32) -> bool {
+        if self.from_hour < self.to_hour {
+            (hour >= self.from_hour) & (hour < self.to_hour)
+        } else {
+            (hour >= self.from_hour) | (hour < self.to_hour)
+        }
+    }
+}
+
+
+/// The multiplier policies a room can be created with.
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+#[serde(tag = "policy", rename_all = "snake_case")]
+pub enum MultiplierKind {
+    /// `Log10Policy`.
+    #[default]
+    Log10,
+
+    /// `CappedPolicy`.
+    Capped {
+        max: f64,
+    },
+
+    /// `StepsPolicy`, the steps must be in order of members.
+    Steps {
+        steps: Vec<Step>,
+    },
+
+    /// `FixedPolicy`.
+    Fixed {
+        value: f64,
+    },
+
+    /// `TimeOfDayPolicy`.
+    TimeOfDay {
+        boosts: Vec<Boost>,
+    },
+
+    /// `HostBonusPolicy`.
+    HostBonus {
+        bonus: f64,
+    },
+}
+
+impl MultiplierKind {
+    /// The name of the policy as it is given when creating a room.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Log10 => "log10",
+            Self::Capped { .. } => "capped",
+            Self::Steps { .. } => "steps",
+            Self::Fixed { .. } => "fixed",
+            Self::TimeOfDay { .. } => "time_of_day",
+            Self::HostBonus { .. } => "host_bonus",
+        }
+    }
+
+    /// Checks the policy's settings make sense.
+    pub fn validate(&self) -> Result<(), &'static str> {
+        let valid = |value: f64| value.is_finite() & (value >= 0.0);
+
+        match self {
+            Self::Log10 => Ok(()),
+            Self::Capped { max } if !valid(*max) => {
+                Err("The multiplier cap must be a positive number!")
+            },
+            Self::Steps { steps } if steps.iter().any(|step| !valid(step.multiplier)) => {
+                Err("Step multipliers must be positive numbers!")
+            },
+            Self::Steps { steps } if steps.windows(2).any(|w| w[0].members >= w[1].members) => {
+                Err("Steps must be in order of members!")
+            },
+            Self::Fixed { value } if !valid(*value) => {
+                Err("The fixed multiplier must be a positive number!")
+            },
+            Self::TimeOfDay { boosts } if boosts.iter().any(|boost| !valid(boost.multiplier)) => {
+                Err("Boost multipliers must be positive numbers!")
+            },
+            Self::TimeOfDay { boosts } if boosts.iter().any(|boost| {
+                (boost.from_hour >= 24) | (boost.to_hour >= 24) | (boost.from_hour == boost.to_hour)
+            }) => {
+                Err("Boost hours must be different hours from 0 to 23!")
+            },
+            Self::HostBonus { bonus } if !valid(*bonus) => {
+                Err("The host bonus must be a positive number!")
+            },
+            _ => Ok(()),
+        }
+    }
+
+    /// Creates the policy.
+    pub fn build(&self) -> Arc<dyn MultiplierPolicy> {
+        match self.clone() {
+            Self::Log10 => Arc::new(Log10Policy),
+            Self::Capped { max } => Arc::new(CappedPolicy { max }),
+            Self::Steps { steps } => Arc::new(StepsPolicy { steps }),
+            Self::Fixed { value } => Arc::new(FixedPolicy { value }),
+            Self::TimeOfDay { boosts } => Arc::new(TimeOfDayPolicy { boosts }),
+            Self::HostBonus { bonus } => Arc::new(HostBonusPolicy { bonus }),
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// An hour in ms, the unix epoch started at midnight UTC.
+    const HOUR: u64 = 3_600_000;
+
+    fn multiplier(kind: &MultiplierKind, members: usize, now: u64) -> f64 {
+        kind.build().multiplier(&MultiplierContext { members, now })
+    }
+
+    /// The multiplier the gateway used before policies, kept in tenths.
+    fn baseline_tenths(members: usize) -> usize {
+        10 + ((members as f32).log10() * 4f32).round() as usize
+    }
+
+    fn step(members: usize, multiplier: f64) -> Step {
+        Step { members, multiplier }
+    }
+
+    fn boost(from_hour: u32, to_hour: u32, multiplier: f64) -> Boost {
+        Boost { from_hour, to_hour, multiplier }
+    }
+
+    #[test]
+    fn log10_matches_the_original_formula() {
+        for members in [1, 10, 100] {
+            let tenths = baseline_tenths(members) as f64 / 10.0;
+            assert!((multiplier(&MultiplierKind::Log10, members, 0) - tenths).abs() < 1e-9);
+        }
+
+        assert!((multiplier(&MultiplierKind::Log10, 1, 0) - 1.0).abs() < 1e-9);
+        assert!((multiplier(&MultiplierKind::Log10, 10, 0) - 1.4).abs() < 1e-9);
+        assert!((multiplier(&MultiplierKind::Log10, 100, 0) - 1.8).abs() < 1e-9);
+    }
+
+    #[test]
+    fn log10_of_an_empty_room_is_no_bonus() {
+        assert_eq!(multiplier(&MultiplierKind::Log10, 0, 0), 1.0);
+    }
+
+    #[test]
+    fn capped_never_goes_above_max() {
+        let kind = MultiplierKind::Capped { max: 1.5 };
+        assert!((multiplier(&kind, 10, 0) - 1.4).abs() < 1e-9);
+        assert_eq!(multiplier(&kind, 100, 0), 1.5);
+        assert_eq!(multiplier(&kind, 10_000, 0), 1.5);
+    }
+
+    #[test]
+    fn steps_use_the_highest_step_reached() {
+        let kind = MultiplierKind::Steps {
+            steps: vec![step(5, 1.2), step(20, 1.5), step(50, 2.0)],
+        };
+
+        assert_eq!(multiplier(&kind, 4, 0), 1.0);
+        assert_eq!(multiplier(&kind, 5, 0), 1.2);
+        assert_eq!(multiplier(&kind, 49, 0), 1.5);
+        assert_eq!(multiplier(&kind, 50, 0), 2.0);
+        assert_eq!(multiplier(&kind, 500, 0), 2.0);
+    }
+
+    #[test]
+    fn boost_covers_its_hours() {
+        let evening = boost(18, 22, 2.0);
+        assert!(!evening.covers(17));
+        assert!(evening.covers(18));
+        assert!(evening.covers(21));
+        assert!(!evening.covers(22));
+    }
+
+    #[test]
+    fn boost_covers_hours_past_midnight() {
+        let night = boost(22, 2, 2.0);
+        assert!(!night.covers(21));
+        assert!(night.covers(22));
+        assert!(night.covers(23));
+        assert!(night.covers(0));
+        assert!(night.covers(1));
+        assert!(!night.covers(2));
+        assert!(!night.covers(12));
+    }
+
+    #[test]
+    fn time_of_day_boosts_the_original_formula() {
+        let kind = MultiplierKind::TimeOfDay {
+            boosts: vec![boost(22, 2, 2.0), boost(23, 1, 3.0)],
+        };
+
+        assert!((multiplier(&kind, 10, 12 * HOUR) - 1.4).abs() < 1e-9);
+        assert!((multiplier(&kind, 10, 23 * HOUR) - 2.8).abs() < 1e-9);
+        assert!((multiplier(&kind, 10, 25 * HOUR) - 2.8).abs() < 1e-9);
+    }
+
+    #[test]
+    fn host_bonus_is_added_to_the_original_formula() {
+        let kind = MultiplierKind::HostBonus { bonus: 0.5 };
+        assert!((multiplier(&kind, 10, 0) - 1.9).abs() < 1e-9);
+    }
+
+    #[test]
+    fn validate_accepts_sensible_policies() {
+        assert!(MultiplierKind::Log10.validate().is_ok());
+        assert!(MultiplierKind::Capped { max: 2.0 }.validate().is_ok());
+        assert!(MultiplierKind::Steps { steps: vec![step(1, 1.0), step(5, 1.5)] }.validate().is_ok());
+        assert!(MultiplierKind::Fixed { value: 0.0 }.validate().is_ok());
+        assert!(MultiplierKind::TimeOfDay { boosts: vec![boost(22, 2, 2.0)] }.validate().is_ok());
+        assert!(MultiplierKind::HostBonus { bonus: 0.5 }.validate().is_ok());
+    }
+
+    #[test]
+    fn validate_refuses_bad_policies() {
+        let bad = [
+            MultiplierKind::Capped { max: -1.0 },
+            MultiplierKind::Capped { max: f64::INFINITY },
+            MultiplierKind::Steps { steps: vec![step(5, 1.5), step(1, 1.0)] },
+            MultiplierKind::Steps { steps: vec![step(5, 1.5), step(5, 2.0)] },
+            MultiplierKind::Steps { steps: vec![step(1, f64::NAN)] },
+            MultiplierKind::Fixed { value: -0.5 },
+            MultiplierKind::TimeOfDay { boosts: vec![boost(22, 24, 2.0)] },
+            MultiplierKind::TimeOfDay { boosts: vec![boost(3, 3, 2.0)] },
+            MultiplierKind::TimeOfDay { boosts: vec![boost(1, 2, -2.0)] },
+            MultiplierKind::HostBonus { bonus: f64::NAN },
+        ];
+
+        for kind in bad {
+            assert!(kind.validate().is_err(), "{:?} should be refused", kind);
+        }
+    }
+
+    #[test]
+    fn policies_are_named_by_their_tag() {
+        let kind: MultiplierKind = serde_json::from_str(r#"{"policy":"capped","max":2.0}"#).unwrap();
+        assert_eq!(kind, MultiplierKind::Capped { max: 2.0 });
+        assert_eq!(kind.as_str(), "capped");
+
+        let kind: MultiplierKind = serde_json::from_str(r#"{"policy":"host_bonus","bonus":1.0}"#).unwrap();
+        assert_eq!(kind.as_str(), "host_bonus");
+    }
+}
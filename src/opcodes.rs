@@ -4,4 +4,37 @@ pub type OpCode = usize;
 
 pub const OP_STATS_UPDATE: OpCode = 0;
 pub const OP_MESSAGE: OpCode = 5;
-pub const OP_LIVE_READY: OpCode = 2;
\ No newline at end of file
+pub const OP_LIVE_READY: OpCode = 2;
+pub const OP_LIVE_ENDED: OpCode = 3;
+pub const OP_INVALID: OpCode = 6;
+pub const OP_HELLO: OpCode = 10;
+pub const OP_READY: OpCode = 12;
+pub const OP_PRESENCE_SNAPSHOT: OpCode = 13;
+pub const OP_PRESENCE_JOIN: OpCode = 14;
+pub const OP_PRESENCE_LEAVE: OpCode = 15;
+pub const OP_HEARTBEAT_ACK: OpCode = 16;
+pub const OP_RESUMED: OpCode = 18;
+pub const OP_RESYNC: OpCode = 19;
+pub const OP_LIVE_STARTING: OpCode = 20;
+pub const OP_LIVE_STALLED: OpCode = 21;
+pub const OP_RECONNECT: OpCode = 22;
+pub const OP_BANDWIDTH_WARNING: OpCode = 23;
+
+// Sent by clients.
+pub const OP_HEARTBEAT: OpCode = 1;
+pub const OP_SEND_MESSAGE: OpCode = 7;
+pub const OP_IDENTIFY: OpCode = 11;
+pub const OP_RESUME: OpCode = 17;
+
+
+pub type CloseCode = u16;
+
+pub const CLOSE_SERVICE_RESTART: CloseCode = 1012;
+
+pub const CLOSE_NOT_AUTHENTICATED: CloseCode = 4003;
+pub const CLOSE_AUTHENTICATION_FAILED: CloseCode = 4004;
+pub const CLOSE_INVALID_SESSION: CloseCode = 4007;
+pub const CLOSE_SESSION_TIMED_OUT: CloseCode = 4009;
+pub const CLOSE_LAGGED: CloseCode = 4010;
+pub const CLOSE_ROOM_CLOSED: CloseCode = 4011;
+pub const CLOSE_UNSUPPORTED_VERSION: CloseCode = 4012;
\ No newline at end of file
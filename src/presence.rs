@@ -0,0 +1,135 @@
+use std::collections::HashMap;
+
+use crate::identity::UserProfile;
+
+
+/// A user in the room and how many connections they have open.
+struct PresenceEntry {
+    /// The user's profile from their most recent connection.
+    profile: UserProfile,
+
+    /// The amount of open connections the user has in the room.
+    connections: usize,
+}
+
+
+/// Tracks which users are connected to a room.
+///
+/// A user with several tabs open has several connections but is only
+/// counted once, they join on their first connection and leave when
+/// their last one closes.
+#[derive(Default)]
+pub struct Presence {
+    /// Maps a connection id to the user id it identified as.
+    sessions: HashMap<u64, String>,
+
+    /// Maps a user id to their presence.
+    users: HashMap<String, PresenceEntry>,
+}
+
+impl Presence {
+    /// Adds a connection for the user, returning true if this is the
+    /// user's first connection in the room.
+    pub fn join(&mut self, connection_id: u64, user: UserProfile) -> bool {
+        self.sessions.insert(connection_id, user.id.clone());
+
+        if let Some(entry) = self.users.get_mut(&user.id) {
+            entry.connections += 1;
+            entry.profile = user;
+            return false
+        }
+
+        self.users.insert(user.id.clone(), PresenceEntry {
+            profile: user,
+            connections: 1,
+        });
+
+        true
+    }
+
+    /// Removes a connection, returning the user if this was their last
+    /// connection in the room.
+    pub fn leave(&mut self, connection_id: u64) -> Option<UserProfile> {
+        let user_id = self.sessions.remove(&connection_id)?;
+        let entry = self.users.get_mut(&user_id)?;
+
+        entry.connections -= 1;
+        if entry.connections > 0 {
+            return None
+        }
+
+        self.users.remove(&user_id).map(|entry| entry.profile)
+    }
+
+    /// The amount of unique users in the room.
+    pub fn user_count(&self) -> usize {
+        self.users.len()
+    }
+
+    /// Every unique user in the room.
+    pub fn users(&self) -> Vec<UserProfile> {
+        self.users
+            .values()
+            .map(|entry| entry.profile.clone())
+            .collect()
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn user(id: &str, display_name: &str) -> UserProfile {
+        UserProfile {
+            id: id.to_string(),
+            display_name: display_name.to_string(),
+            avatar: None,
+        }
+    }
+
+    #[test]
+    fn extra_tabs_are_counted_once() {
+        let mut presence = Presence::default();
+
+        assert!(presence.join(1, user("a", "A")));
+        assert!(!presence.join(2, user("a", "A")));
+        assert!(presence.join(3, user("b", "B")));
+        assert_eq!(presence.user_count(), 2);
+    }
+
+    #[test]
+    fn user_leaves_with_their_last_tab() {
+        let mut presence = Presence::default();
+        presence.join(1, user("a", "A"));
+        presence.join(2, user("a", "A"));
+
+        assert!(presence.leave(1).is_none());
+        assert_eq!(presence.user_count(), 1);
+
+        let left = presence.leave(2).expect("last tab closed");
+        assert_eq!(left.id, "a");
+        assert_eq!(presence.user_count(), 0);
+    }
+
+    #[test]
+    fn unknown_connection_leaving_is_ignored() {
+        let mut presence = Presence::default();
+        presence.join(1, user("a", "A"));
+
+        assert!(presence.leave(2).is_none());
+        assert!(presence.leave(1).is_some());
+        assert!(presence.leave(1).is_none());
+    }
+
+    #[test]
+    fn profile_is_from_the_latest_tab() {
+        let mut presence = Presence::default();
+        presence.join(1, user("a", "Old"));
+        presence.join(2, user("a", "New"));
+
+        let users = presence.users();
+        assert_eq!(users.len(), 1);
+        assert_eq!(users[0].display_name, "New");
+    }
+}
@@ -0,0 +1,222 @@
+use tokio::time::{Duration, Instant};
+
+use std::collections::{HashMap, VecDeque};
+use std::sync::Arc;
+
+
+/// An event that has been broadcast to a room.
+#[derive(Debug)]
+pub struct RoomEvent {
+    /// The room wide sequence number of the event.
+    pub seq: u64,
+
+    /// The serialized frame, already containing the sequence number.
+    pub frame: String,
+}
+
+
+/// A bounded buffer of the most recent events broadcast to a room,
+/// this is what missed events are replayed from when a client resumes.
+pub struct EventHistory {
+    /// The sequence number given to the next event.
+    next_seq: u64,
+
+    /// The buffered events, oldest first.
+    events: VecDeque<Arc<RoomEvent>>,
+
+    /// The max amount of events kept.
+    capacity: usize,
+}
+
+impl EventHistory {
+    /// Creates an empty history, the first event will have a seq of 1.
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            next_seq: 1,
+            events: VecDeque::with_capacity(capacity),
+            capacity,
+        }
+    }
+
+    /// Gives the next event a sequence number and stores it, dropping the
+    /// oldest event if the buffer is full.
+    pub fn push(&mut self, make_frame: impl FnOnce(u64) -> String) -> Arc<RoomEvent> {
+        let seq = self.next_seq;
+        self.next_seq += 1;
+
+        let event = Arc::new(RoomEvent {
+            seq,
+            frame: make_frame(seq),
+        });
+
+        if self.events.len() >= self.capacity {
+            self.events.pop_front();
+        }
+        self.events.push_back(event.clone());
+
+        event
+    }
+
+    /// The sequence number of the most recent event, 0 if there are none.
+    pub fn last_seq(&self) -> u64 {
+        self.next_seq - 1
+    }
+
+    /// Every buffered event after the given sequence number.
+    ///
+    /// Returns None if some of the events after `seq` have already been
+    /// dropped or `seq` has not happened yet, either way the missed events
+    /// can not be replayed.
+    pub fn since(&self, seq: u64) -> Option<Vec<Arc<RoomEvent>>> {
+        if seq >= self.next_seq {
+            return None
+        }
+
+        let oldest = self.events
+            .front()
+            .map(|event| event.seq)
+            .unwrap_or(self.next_seq);
+
+        if seq + 1 < oldest {
+            return None
+        }
+
+        let missed = self.events
+            .iter()
+            .filter(|event| event.seq > seq)
+            .cloned()
+            .collect();
+
+        Some(missed)
+    }
+}
+
+
+/// A session that can be resumed.
+struct SessionEntry {
+    /// The connection id the session keeps across reconnects.
+    connection_id: u64,
+
+    /// The user the session belongs to.
+    user_id: String,
+
+    /// When the session's connection dropped, None while it is connected.
+    suspended_at: Option<Instant>,
+}
+
+
+/// Tracks the sessions in a room so a client that briefly disconnects can
+/// pick up where it left off instead of re-joining.
+#[derive(Default)]
+pub struct Sessions {
+    sessions: HashMap<String, SessionEntry>,
+}
+
+impl Sessions {
+    /// Registers a newly identified session.
+    pub fn open(&mut self, session_id: String, connection_id: u64, user_id: String) {
+        self.sessions.insert(session_id, SessionEntry {
+            connection_id,
+            user_id,
+            suspended_at: None,
+        });
+    }
+
+    /// Marks a session's connection as dropped.
+    pub fn suspend(&mut self, session_id: &str) {
+        if let Some(entry) = self.sessions.get_mut(session_id) {
+            entry.suspended_at = Some(Instant::now());
+        }
+    }
+
+    /// Takes back a suspended session, returning its connection id.
+    ///
+    /// This fails if the session does not exist, is still connected or
+    /// belongs to someone else.
+    pub fn resume(&mut self, session_id: &str, user_id: &str) -> Option<u64> {
+        let entry = self.sessions.get_mut(session_id)?;
+
+        if (entry.user_id != user_id) | entry.suspended_at.is_none() {
+            return None
+        }
+
+        entry.suspended_at = None;
+        Some(entry.connection_id)
+    }
+
+    /// Removes a session, returning its connection id.
+    pub fn close(&mut self, session_id: &str) -> Option<u64> {
+        self.sessions
+            .remove(session_id)
+            .map(|entry| entry.connection_id)
+    }
+
+    /// Removes a session if it has been suspended for longer than the
+    /// grace period, returning its connection id.
+    pub fn expire(&mut self, session_id: &str, grace: Duration) -> Option<u64> {
+        let suspended_at = self.sessions.get(session_id)?.suspended_at?;
+
+        if suspended_at.elapsed() < grace {
+            return None
+        }
+
+        self.close(session_id)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn history(capacity: usize, events: u64) -> EventHistory {
+        let mut history = EventHistory::new(capacity);
+        for _ in 0..events {
+            history.push(|seq| seq.to_string());
+        }
+        history
+    }
+
+    fn seqs(events: Option<Vec<Arc<RoomEvent>>>) -> Option<Vec<u64>> {
+        events.map(|events| events.iter().map(|event| event.seq).collect())
+    }
+
+    #[test]
+    fn empty_history_has_nothing_missed() {
+        let history = history(3, 0);
+        assert_eq!(history.last_seq(), 0);
+        assert_eq!(seqs(history.since(0)), Some(vec![]));
+        assert_eq!(seqs(history.since(1)), None);
+    }
+
+    #[test]
+    fn since_gives_events_after_seq() {
+        let history = history(5, 3);
+        assert_eq!(history.last_seq(), 3);
+        assert_eq!(seqs(history.since(0)), Some(vec![1, 2, 3]));
+        assert_eq!(seqs(history.since(2)), Some(vec![3]));
+        assert_eq!(seqs(history.since(3)), Some(vec![]));
+    }
+
+    #[test]
+    fn since_a_future_seq_can_not_be_replayed() {
+        let history = history(5, 3);
+        assert_eq!(seqs(history.since(4)), None);
+    }
+
+    #[test]
+    fn since_a_dropped_seq_can_not_be_replayed() {
+        let history = history(3, 5);
+        assert_eq!(seqs(history.since(1)), None);
+        assert_eq!(seqs(history.since(2)), Some(vec![3, 4, 5]));
+        assert_eq!(seqs(history.since(5)), Some(vec![]));
+    }
+
+    #[test]
+    fn frames_are_made_with_their_seq() {
+        let mut history = EventHistory::new(3);
+        let event = history.push(|seq| format!("event {}", seq));
+        assert_eq!(event.seq, 1);
+        assert_eq!(event.frame, "event 1");
+    }
+}
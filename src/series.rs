@@ -0,0 +1,199 @@
+use serde::{Serialize, Deserialize};
+
+use std::collections::VecDeque;
+
+
+/// A room's stats at the time its stream was sampled.
+#[derive(Debug, Clone, Serialize)]
+pub struct StatsPoint {
+    /// When the stream was sampled as a unix timestamp in ms, or the
+    /// start of the bucket when downsampled.
+    pub at: u64,
+
+    /// The stream's rate of transfer in bytes per second.
+    pub bytes_per_sec: usize,
+
+    /// The stream's average rate in bytes per second.
+    pub avg_bytes_per_sec: usize,
+
+    /// The total amount of bytes streamed.
+    pub total_bytes: usize,
+
+    /// The amount of members in the room.
+    pub members: usize,
+
+    /// The room's multiplier.
+    pub multiplier: f64,
+}
+
+
+/// The range and resolution of a room's stats history to get.
+#[derive(Debug, Default, Deserialize)]
+pub struct HistoryQuery {
+    /// Only points at or after this unix timestamp in ms.
+    pub from: Option<u64>,
+
+    /// Only points at or before this unix timestamp in ms.
+    pub to: Option<u64>,
+
+    /// Averages the points into buckets of this many seconds, every
+    /// point is given if not set.
+    pub resolution: Option<u64>,
+}
+
+
+/// A room's stats history as returned by the api.
+#[derive(Debug, Serialize)]
+pub struct StatsHistory {
+    pub room_id: String,
+
+    /// The seconds each point covers, None if they are the raw samples.
+    pub resolution: Option<u64>,
+
+    /// The points in order of time.
+    pub points: Vec<StatsPoint>,
+}
+
+
+/// The most recent samples of a room's stats, forgetting the oldest once
+/// there are more than `STATS_HISTORY_SIZE`.
+pub struct StatsSeries {
+    points: VecDeque<StatsPoint>,
+    capacity: usize,
+}
+
+impl StatsSeries {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            points: VecDeque::new(),
+            capacity,
+        }
+    }
+
+    /// Adds a sample.
+    pub fn push(&mut self, point: StatsPoint) {
+        self.points.push_back(point);
+        while self.points.len() > self.capacity {
+            self.points.pop_front();
+        }
+    }
+
+    /// The points in the query's range, averaged into buckets if it gives
+    /// a resolution.
+    ///
+    /// A bucket's rates, members and multiplier are the mean of the
+    /// points in it and its total bytes are the last point's.
+    pub fn query(&self, query: &HistoryQuery) -> Vec<StatsPoint> {
+        let from = query.from.unwrap_or(0);
+        let to = query.to.unwrap_or(u64::MAX);
+        let in_range = self.points
+            .iter()
+            .filter(|point| (point.at >= from) & (point.at <= to));
+
+        let bucket_size = match query.resolution {
+            Some(resolution) if resolution > 0 => resolution.saturating_mul(1000),
+            _ => return in_range.cloned().collect(),
+        };
+
+        let mut buckets: Vec<(u64, Vec<&StatsPoint>)> = Vec::new();
+        for point in in_range {
+            let start = point.at - point.at % bucket_size;
+            match buckets.last_mut() {
+                Some((bucket_start, bucket)) if *bucket_start == start => bucket.push(point),
+                _ => buckets.push((start, vec![point])),
+            }
+        }
+
+        buckets
+            .into_iter()
+            .map(|(start, bucket)| average(start, &bucket))
+            .collect()
+    }
+}
+
+
+/// Averages the points in the bucket starting at `start` into one.
+fn average(start: u64, bucket: &[&StatsPoint]) -> StatsPoint {
+    let count = bucket.len();
+    let mean = |value: fn(&StatsPoint) -> f64| {
+        bucket.iter().map(|point| value(point)).sum::<f64>() / count as f64
+    };
+
+    StatsPoint {
+        at: start,
+        bytes_per_sec: mean(|point| point.bytes_per_sec as f64).round() as usize,
+        avg_bytes_per_sec: mean(|point| point.avg_bytes_per_sec as f64).round() as usize,
+        total_bytes: bucket[count - 1].total_bytes,
+        members: mean(|point| point.members as f64).round() as usize,
+        multiplier: mean(|point| point.multiplier),
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn point(at: u64, bytes_per_sec: usize, total_bytes: usize) -> StatsPoint {
+        StatsPoint {
+            at,
+            bytes_per_sec,
+            avg_bytes_per_sec: bytes_per_sec,
+            total_bytes,
+            members: 1,
+            multiplier: 1.0,
+        }
+    }
+
+    fn series(points: &[(u64, usize, usize)]) -> StatsSeries {
+        let mut series = StatsSeries::new(10);
+        for &(at, rate, total) in points {
+            series.push(point(at, rate, total));
+        }
+        series
+    }
+
+    fn query(from: Option<u64>, to: Option<u64>, resolution: Option<u64>) -> HistoryQuery {
+        HistoryQuery { from, to, resolution }
+    }
+
+    #[test]
+    fn oldest_points_are_forgotten() {
+        let mut series = StatsSeries::new(2);
+        series.push(point(1, 0, 0));
+        series.push(point(2, 0, 0));
+        series.push(point(3, 0, 0));
+
+        let at: Vec<u64> = series.query(&query(None, None, None)).iter().map(|p| p.at).collect();
+        assert_eq!(at, vec![2, 3]);
+    }
+
+    #[test]
+    fn range_is_inclusive() {
+        let series = series(&[(1000, 0, 0), (2000, 0, 0), (3000, 0, 0), (4000, 0, 0)]);
+
+        let points = series.query(&query(Some(2000), Some(3000), None));
+        let at: Vec<u64> = points.iter().map(|p| p.at).collect();
+        assert_eq!(at, vec![2000, 3000]);
+    }
+
+    #[test]
+    fn points_are_averaged_into_buckets() {
+        let series = series(&[(0, 100, 10), (500, 200, 20), (1000, 50, 30), (2500, 10, 40)]);
+
+        let points = series.query(&query(None, None, Some(1)));
+        let at: Vec<u64> = points.iter().map(|p| p.at).collect();
+        assert_eq!(at, vec![0, 1000, 2000]);
+
+        assert_eq!(points[0].bytes_per_sec, 150);
+        assert_eq!(points[0].total_bytes, 20);
+        assert_eq!(points[1].bytes_per_sec, 50);
+        assert_eq!(points[2].total_bytes, 40);
+    }
+
+    #[test]
+    fn zero_resolution_gives_the_raw_points() {
+        let series = series(&[(0, 100, 10), (500, 200, 20)]);
+        assert_eq!(series.query(&query(None, None, Some(0))).len(), 2);
+    }
+}
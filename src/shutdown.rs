@@ -0,0 +1,76 @@
+use tokio::sync::watch;
+
+use std::sync::Arc;
+
+
+/// How far through shutting down the gateway is.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Phase {
+    /// The gateway is serving normally.
+    Running,
+
+    /// New upgrades are refused, clients have been told to reconnect
+    /// elsewhere and the stats watchers stop at their next sleep.
+    Draining,
+
+    /// The drain period is over and every remaining connection is closed.
+    Closing,
+}
+
+
+/// A handle to the gateway's shutdown phase that tasks can watch.
+#[derive(Clone)]
+pub struct Shutdown {
+    tx: Arc<watch::Sender<Phase>>,
+}
+
+impl Shutdown {
+    pub fn new() -> Self {
+        let (tx, _) = watch::channel(Phase::Running);
+        Self {
+            tx: Arc::new(tx),
+        }
+    }
+
+    /// The current phase.
+    pub fn phase(&self) -> Phase {
+        *self.tx.borrow()
+    }
+
+    /// If the gateway has started shutting down.
+    pub fn is_draining(&self) -> bool {
+        self.phase() != Phase::Running
+    }
+
+    /// Moves to the given phase, waking every watcher.
+    pub fn set(&self, phase: Phase) {
+        self.tx.send_replace(phase);
+    }
+
+    /// Subscribes to changes of the phase.
+    pub fn subscribe(&self) -> watch::Receiver<Phase> {
+        self.tx.subscribe()
+    }
+}
+
+
+/// Waits for a SIGTERM or ctrl-c.
+pub async fn signal() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+
+        let mut sigterm = signal(SignalKind::terminate())
+            .expect("failed to listen for SIGTERM");
+
+        tokio::select! {
+            _ = sigterm.recv() => {},
+            _ = tokio::signal::ctrl_c() => {},
+        }
+    }
+
+    #[cfg(not(unix))]
+    {
+        let _ = tokio::signal::ctrl_c().await;
+    }
+}
@@ -0,0 +1,167 @@
+use futures::future::BoxFuture;
+use serde::{Serialize, Deserialize};
+
+use std::fmt;
+use std::sync::Arc;
+
+mod http;
+mod mock;
+mod push;
+mod xml;
+
+pub use self::http::HttpStatsSource;
+pub use self::mock::MockStatsSource;
+pub use self::push::PushStatsSource;
+pub use self::xml::XmlStatsSource;
+
+
+/// A single sample of a live stream's stats.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StatsSample {
+    /// The total amount of bytes streamed, audio and video included.
+    pub total_bytes: usize,
+
+    /// The current rate of transfer in bytes per second.
+    pub bytes_per_sec: usize,
+}
+
+
+/// What a stats source knows about a room's stream.
+#[derive(Debug, Clone)]
+pub enum StreamStatus {
+    /// The stream is live with the given stats.
+    Live(StatsSample),
+
+    /// Nothing is being streamed to the room.
+    NotLive,
+}
+
+
+/// The reasons a stats source can fail to get a room's stats.
+#[derive(Debug)]
+pub enum StatsError {
+    /// The live server could not be reached.
+    Request(String),
+
+    /// The live server responded with an unexpected status.
+    Status {
+        status: u16,
+        detail: String,
+    },
+
+    /// The live server's response could not be understood.
+    Parse(String),
+}
+
+impl StatsError {
+    /// If the error counts towards the watcher giving up, a server that
+    /// responds with an error is still there so it is only waited on.
+    pub fn is_fatal(&self) -> bool {
+        !matches!(self, Self::Status { .. })
+    }
+
+    /// The kind of failure.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Self::Request(_) => "request",
+            Self::Status { .. } => "status",
+            Self::Parse(_) => "parse",
+        }
+    }
+
+    /// The http status the live server responded with, if it did.
+    pub fn status(&self) -> Option<u16> {
+        match self {
+            Self::Status { status, .. } => Some(*status),
+            _ => None,
+        }
+    }
+
+    /// What went wrong.
+    pub fn detail(&self) -> &str {
+        match self {
+            Self::Request(detail) => detail,
+            Self::Status { detail, .. } => detail,
+            Self::Parse(detail) => detail,
+        }
+    }
+
+    /// The http status of the error or the kind of failure if there was
+    /// no response, used to label the error in the metrics.
+    pub fn status_label(&self) -> String {
+        match self {
+            Self::Request(_) => "request".to_string(),
+            Self::Status { status, .. } => status.to_string(),
+            Self::Parse(_) => "parse".to_string(),
+        }
+    }
+}
+
+impl fmt::Display for StatsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Request(e) => write!(f, "request failed: {}", e),
+            Self::Status { status, detail } => {
+                write!(f, "unexpected status: {}, Msg: {}", status, detail)
+            },
+            Self::Parse(e) => write!(f, "invalid stats: {}", e),
+        }
+    }
+}
+
+
+/// Somewhere a room's stream stats come from.
+///
+/// The room's stats watcher polls the source every so often, push based
+/// sources are handed stats as they arrive and give back the latest.
+pub trait StatsSource: Send + Sync {
+    /// Gets the current status of the room's stream.
+    fn poll(&self) -> BoxFuture<'_, Result<StreamStatus, StatsError>>;
+
+    /// Hands the source stats that were pushed to the gateway, returning
+    /// false if the source does not take pushed stats.
+    fn push(&self, _status: StreamStatus) -> bool {
+        false
+    }
+}
+
+
+/// The kinds of stats source a room can be created with.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StatsBackend {
+    /// Poll the live server's JSON stats api.
+    #[default]
+    Http,
+
+    /// Poll an nginx-rtmp / SRS style XML stats page.
+    Xml,
+
+    /// Wait for stats to be pushed to the gateway.
+    Push,
+
+    /// Make up a steady stream, for testing without a live server.
+    Mock,
+}
+
+impl StatsBackend {
+    /// The name of the backend as it is given when creating a room.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Http => "http",
+            Self::Xml => "xml",
+            Self::Push => "push",
+            Self::Mock => "mock",
+        }
+    }
+
+    /// Creates the stats source for a room.
+    pub fn build(self, live_server: &str, room_id: &str) -> Arc<dyn StatsSource> {
+        match self {
+            Self::Http => Arc::new(HttpStatsSource::new(live_server, room_id)),
+            Self::Xml => Arc::new(XmlStatsSource::new(live_server, room_id)),
+            Self::Push => Arc::new(PushStatsSource::new()),
+            Self::Mock => Arc::new(MockStatsSource::new()),
+        }
+    }
+}
@@ -0,0 +1,126 @@
+use futures::future::BoxFuture;
+use futures::FutureExt;
+use serde::Deserialize;
+use serde_json::Value;
+use warp::http::StatusCode;
+
+use super::{StatsError, StatsSample, StatsSource, StreamStatus};
+use crate::config;
+
+
+/// The streaming server stats response
+#[allow(unused)]
+#[derive(Deserialize)]
+struct StreamStatsResponse {
+    /// The http status.
+    status: u16,
+
+    /// Any relevant info returned by the response.
+    data: Value,
+}
+
+
+/// The stream statistics.
+///
+/// This just wraps the resulting JSON given by the live streaming server
+/// and changes constantly.
+#[allow(unused)]
+#[derive(Deserialize)]
+struct StreamStats {
+    /// The key/room_name string that the server identifies a session with.
+    key: String,
+
+    /// The RTMP input url to publish data to the server.
+    url: String,
+
+    /// The uid of a stream publisher.
+    stream_id: u32,
+
+    /// The total amount of bytes sent to the server in video form.
+    video_total_bytes: usize,
+
+    /// The bitrate of the video in bytes/sec.
+    video_speed: usize,  // todo maybe rename this???
+
+    /// The total amount of bytes sent to the data in audio form.
+    audio_total_bytes: usize,
+
+    /// The bitrate of the audio in bytes/sec.
+    audio_speed: usize,  // todo maybe rename this???
+}
+
+
+/// Polls the live server's `/stats/livestat` JSON api.
+pub struct HttpStatsSource {
+    client: reqwest::Client,
+    url: String,
+}
+
+impl HttpStatsSource {
+    pub fn new(live_server: &str, room_id: &str) -> Self {
+        Self {
+            client: reqwest::Client::new(),
+            url: format!(
+                "{}/stats/livestat?room={}&authorization={}",
+                live_server,
+                room_id,
+                config::get().api_key.as_str(),
+            ),
+        }
+    }
+
+    async fn fetch(&self) -> Result<StreamStatus, StatsError> {
+        let resp = self.client
+            .get(&self.url)
+            .send()
+            .await
+            .map_err(|e| StatsError::Request(e.to_string()))?;
+
+        let status = resp.status();
+        if status == StatusCode::NOT_FOUND {
+            return Ok(StreamStatus::NotLive)
+        }
+
+        let msg = resp
+            .text()
+            .await
+            .unwrap_or_else(|_| "".to_string());
+        let maybe_data = serde_json::from_str::<'_, StreamStatsResponse>(&msg);
+
+        if status != StatusCode::OK {
+            let detail = match maybe_data {
+                // Api responded with a custom error detail.
+                Ok(error_resp) => {
+                    format!("{:?}", error_resp.data)
+                },
+
+                // The api crashed un-expectantly and was unable to produce
+                // a usable detail.
+                Err(_) => {
+                    format!("Error has no details: {}", &msg)
+                }
+            };
+
+            return Err(StatsError::Status {
+                status: status.as_u16(),
+                detail,
+            })
+        }
+
+        let data = maybe_data
+            .map_err(|e| StatsError::Parse(format!("{}, Origin: {}", e, &msg)))?;
+        let stats = serde_json::from_value::<StreamStats>(data.data)
+            .map_err(|e| StatsError::Parse(format!("{}, Origin: {}", e, &msg)))?;
+
+        Ok(StreamStatus::Live(StatsSample {
+            total_bytes: stats.video_total_bytes + stats.audio_total_bytes,
+            bytes_per_sec: ((stats.video_speed + stats.audio_speed) * 1000) / 8,
+        }))
+    }
+}
+
+impl StatsSource for HttpStatsSource {
+    fn poll(&self) -> BoxFuture<'_, Result<StreamStatus, StatsError>> {
+        self.fetch().boxed()
+    }
+}
@@ -0,0 +1,51 @@
+use futures::future::{self, BoxFuture};
+use futures::FutureExt;
+use tokio::time::Instant;
+
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::Ordering::Relaxed;
+
+use super::{StatsError, StatsSample, StatsSource, StreamStatus};
+
+/// The rate the mock stream runs at, roughly a 2Mbps stream.
+const MOCK_BYTES_PER_SEC: usize = 250 * 1024;
+
+
+/// Makes up a steady live stream so rooms can be tested without a live
+/// server, pushing a not live status stops the stream and pushing a live
+/// one starts it again.
+pub struct MockStatsSource {
+    started_at: Instant,
+    live: AtomicBool,
+}
+
+impl MockStatsSource {
+    pub fn new() -> Self {
+        Self {
+            started_at: Instant::now(),
+            live: AtomicBool::new(true),
+        }
+    }
+}
+
+impl StatsSource for MockStatsSource {
+    fn poll(&self) -> BoxFuture<'_, Result<StreamStatus, StatsError>> {
+        let status = if self.live.load(Relaxed) {
+            let elapsed = self.started_at.elapsed().as_secs() as usize;
+
+            StreamStatus::Live(StatsSample {
+                total_bytes: elapsed * MOCK_BYTES_PER_SEC,
+                bytes_per_sec: MOCK_BYTES_PER_SEC,
+            })
+        } else {
+            StreamStatus::NotLive
+        };
+
+        future::ready(Ok(status)).boxed()
+    }
+
+    fn push(&self, status: StreamStatus) -> bool {
+        self.live.store(matches!(status, StreamStatus::Live(_)), Relaxed);
+        true
+    }
+}
@@ -0,0 +1,43 @@
+use futures::future::{self, BoxFuture};
+use futures::FutureExt;
+use tokio::time::{Duration, Instant};
+
+use std::sync::Mutex;
+
+use super::{StatsError, StatsSource, StreamStatus};
+use crate::config;
+
+
+/// Holds the stats most recently pushed to the gateway by the live server.
+///
+/// If nothing has been pushed for `PUSH_STATS_TIMEOUT` seconds the
+/// stream is treated as not live.
+pub struct PushStatsSource {
+    latest: Mutex<Option<(StreamStatus, Instant)>>,
+}
+
+impl PushStatsSource {
+    pub fn new() -> Self {
+        Self {
+            latest: Mutex::new(None),
+        }
+    }
+}
+
+impl StatsSource for PushStatsSource {
+    fn poll(&self) -> BoxFuture<'_, Result<StreamStatus, StatsError>> {
+        let timeout = Duration::from_secs(config::get().push_stats_timeout);
+
+        let status = match self.latest.lock().unwrap().as_ref() {
+            Some((status, pushed_at)) if pushed_at.elapsed() < timeout => status.clone(),
+            _ => StreamStatus::NotLive,
+        };
+
+        future::ready(Ok(status)).boxed()
+    }
+
+    fn push(&self, status: StreamStatus) -> bool {
+        *self.latest.lock().unwrap() = Some((status, Instant::now()));
+        true
+    }
+}
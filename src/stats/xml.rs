@@ -0,0 +1,157 @@
+use futures::future::BoxFuture;
+use futures::FutureExt;
+
+use super::{StatsError, StatsSample, StatsSource, StreamStatus};
+
+
+/// Polls an nginx-rtmp / SRS style XML stats page.
+///
+/// The page is expected to look like nginx-rtmp's `/stat`, the room's
+/// stream is the `<stream>` element whose `<name>` is the room id:
+///
+/// ```xml
+/// <rtmp><server><application><live>
+///     <stream>
+///         <name>room_id</name>
+///         <bw_in>2500000</bw_in>
+///         <bytes_in>104857600</bytes_in>
+///     </stream>
+/// </live></application></server></rtmp>
+/// ```
+pub struct XmlStatsSource {
+    client: reqwest::Client,
+    url: String,
+    room_id: String,
+}
+
+impl XmlStatsSource {
+    pub fn new(live_server: &str, room_id: &str) -> Self {
+        Self {
+            client: reqwest::Client::new(),
+            url: format!("{}/stat", live_server),
+            room_id: room_id.to_string(),
+        }
+    }
+
+    async fn fetch(&self) -> Result<StreamStatus, StatsError> {
+        let resp = self.client
+            .get(&self.url)
+            .send()
+            .await
+            .map_err(|e| StatsError::Request(e.to_string()))?;
+
+        let status = resp.status();
+        let body = resp
+            .text()
+            .await
+            .unwrap_or_else(|_| "".to_string());
+
+        if !status.is_success() {
+            return Err(StatsError::Status {
+                status: status.as_u16(),
+                detail: body,
+            })
+        }
+
+        parse_stream(&body, &self.room_id)
+    }
+}
+
+impl StatsSource for XmlStatsSource {
+    fn poll(&self) -> BoxFuture<'_, Result<StreamStatus, StatsError>> {
+        self.fetch().boxed()
+    }
+}
+
+
+/// Finds the room's stream in the stats page, if it is not listed the
+/// room is not live.
+fn parse_stream(body: &str, room_id: &str) -> Result<StreamStatus, StatsError> {
+    let doc = roxmltree::Document::parse(body)
+        .map_err(|e| StatsError::Parse(e.to_string()))?;
+
+    let stream = doc
+        .descendants()
+        .filter(|node| node.has_tag_name("stream"))
+        .find(|node| child_text(node, "name") == Some(room_id));
+
+    let stream = match stream {
+        Some(stream) => stream,
+        None => return Ok(StreamStatus::NotLive),
+    };
+
+    let total_bytes = child_number(&stream, "bytes_in")?;
+    let bits_per_sec = child_number(&stream, "bw_in")?;
+
+    Ok(StreamStatus::Live(StatsSample {
+        total_bytes,
+        bytes_per_sec: bits_per_sec / 8,
+    }))
+}
+
+
+fn child_text<'a>(node: &roxmltree::Node<'a, '_>, tag: &str) -> Option<&'a str> {
+    node.children()
+        .find(|child| child.has_tag_name(tag))
+        .and_then(|child| child.text())
+        .map(|text| text.trim())
+}
+
+
+fn child_number(node: &roxmltree::Node, tag: &str) -> Result<usize, StatsError> {
+    child_text(node, tag)
+        .ok_or_else(|| StatsError::Parse(format!("stream is missing <{}>", tag)))?
+        .parse()
+        .map_err(|_| StatsError::Parse(format!("<{}> is not a number", tag)))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PAGE: &str = "
+        <rtmp><server><application><live>
+            <stream>
+                <name>other</name>
+                <bw_in>800</bw_in>
+                <bytes_in>100</bytes_in>
+            </stream>
+            <stream>
+                <name> room </name>
+                <bw_in>8000</bw_in>
+                <bytes_in>5000</bytes_in>
+            </stream>
+        </live></application></server></rtmp>
+    ";
+
+    #[test]
+    fn finds_the_rooms_stream() {
+        match parse_stream(PAGE, "room") {
+            Ok(StreamStatus::Live(sample)) => {
+                assert_eq!(sample.total_bytes, 5000);
+                assert_eq!(sample.bytes_per_sec, 1000);
+            },
+            other => panic!("expected a live stream, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn unlisted_room_is_not_live() {
+        assert!(matches!(parse_stream(PAGE, "missing"), Ok(StreamStatus::NotLive)));
+    }
+
+    #[test]
+    fn missing_or_bad_numbers_fail() {
+        let missing = "<live><stream><name>room</name><bw_in>8</bw_in></stream></live>";
+        assert!(matches!(parse_stream(missing, "room"), Err(StatsError::Parse(_))));
+
+        let bad = "<live><stream><name>room</name><bw_in>x</bw_in><bytes_in>1</bytes_in></stream></live>";
+        assert!(matches!(parse_stream(bad, "room"), Err(StatsError::Parse(_))));
+    }
+
+    #[test]
+    fn invalid_xml_fails() {
+        assert!(matches!(parse_stream("<rtmp>", "room"), Err(StatsError::Parse(_))));
+    }
+}
@@ -0,0 +1,142 @@
+use futures::future::BoxFuture;
+use serde::{Serialize, Deserialize};
+
+use std::fmt;
+use std::sync::Arc;
+
+mod file;
+mod none;
+
+pub use self::file::FileStore;
+pub use self::none::NoStore;
+
+use crate::analytics::ViewerAnalytics;
+use crate::bandwidth::BandwidthState;
+use crate::ledger::{Ledger, PayoutBatch};
+use crate::lifecycle::Lifecycle;
+use crate::multiplier::MultiplierKind;
+use crate::stats::StatsBackend;
+
+
+/// A room's definition and accumulated stats as they are persisted.
+///
+/// Members and sessions are not kept as every connection is lost when
+/// the gateway restarts, clients rejoin and rebuild them. Neither are the
+/// rates the average rate is worked out from as there is one for every
+/// sample, the average starts again from the rates sampled after a
+/// restart.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RoomSnapshot {
+    pub room_id: String,
+    pub live_server: String,
+    pub stats_backend: StatsBackend,
+    pub created_at: u64,
+    pub multiplier: f64,
+
+    /// How the room's multiplier is worked out, rooms saved before
+    /// policies could be chosen use the default.
+    #[serde(default)]
+    pub multiplier_policy: MultiplierKind,
+    pub avg_byte_rate: usize,
+    pub data_streamed: usize,
+    pub last_time_sample: usize,
+    pub stream_time: usize,
+    pub stream: Lifecycle,
+
+    /// The room's own bandwidth limit, rooms saved before limits could
+    /// be set use `ROOM_BYTE_RATE_LIMIT`.
+    #[serde(default)]
+    pub byte_rate_limit: Option<usize>,
+
+    /// The times the room's stream went over a bandwidth limit.
+    #[serde(default)]
+    pub bandwidth: BandwidthState,
+
+    /// The XP earned in the room, rooms saved before XP was tracked have
+    /// none.
+    #[serde(default)]
+    pub ledger: Ledger,
+
+    /// Who has watched the room's streams, rooms saved before viewers
+    /// were counted have no numbers.
+    #[serde(default)]
+    pub viewers: ViewerAnalytics,
+}
+
+
+/// The XP ledger of a deleted room that still had XP waiting to be paid
+/// out.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RetiredLedger {
+    pub room_id: String,
+    pub ledger: Ledger,
+}
+
+
+/// Everything kept between restarts of the gateway.
+#[derive(Debug, Clone, Default)]
+pub struct StoreState {
+    pub rooms: Vec<RoomSnapshot>,
+
+    /// The recent XP payout batches, so a payout retried after a restart
+    /// gets the same batch back.
+    pub payouts: Vec<PayoutBatch>,
+
+    /// The ledgers of deleted rooms waiting for the next payout.
+    pub retired_ledgers: Vec<RetiredLedger>,
+}
+
+
+/// The reasons rooms can fail to be saved or loaded.
+#[derive(Debug)]
+pub enum StoreError {
+    /// The store could not be read or written.
+    Io(std::io::Error),
+
+    /// The stored rooms could not be encoded or decoded.
+    Format(serde_json::Error),
+}
+
+impl fmt::Display for StoreError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io(e) => write!(f, "io error: {}", e),
+            Self::Format(e) => write!(f, "invalid room data: {}", e),
+        }
+    }
+}
+
+
+/// Somewhere rooms are kept between restarts of the gateway.
+pub trait RoomStore: Send + Sync {
+    /// Loads every saved room and payout.
+    fn load(&self) -> BoxFuture<'_, Result<StoreState, StoreError>>;
+
+    /// Replaces the saved rooms and payouts with the given ones.
+    fn save(&self, state: StoreState) -> BoxFuture<'_, Result<(), StoreError>>;
+
+    /// Checks the store can currently be saved to.
+    fn check(&self) -> BoxFuture<'_, Result<(), StoreError>>;
+}
+
+
+/// The kinds of store rooms can be persisted to.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StoreBackend {
+    /// A JSON file at `store_path`.
+    File,
+
+    /// Nothing is persisted.
+    None,
+}
+
+impl StoreBackend {
+    /// Creates the room store.
+    pub fn build(self, path: &str) -> Arc<dyn RoomStore> {
+        match self {
+            Self::File => Arc::new(FileStore::new(path)),
+            Self::None => Arc::new(NoStore),
+        }
+    }
+}
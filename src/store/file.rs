@@ -0,0 +1,139 @@
+use futures::future::BoxFuture;
+use futures::FutureExt;
+use serde::{Serialize, Deserialize};
+use serde::de::Error as DeError;
+use tokio::fs;
+use tokio::io::AsyncWriteExt;
+
+use std::io::{self, ErrorKind};
+use std::path::{Path, PathBuf};
+
+use super::{RetiredLedger, RoomSnapshot, RoomStore, StoreError, StoreState};
+use crate::ledger::PayoutBatch;
+
+/// The version of the file format, bumped if it changes in a way old
+/// files can not be read.
+const FORMAT_VERSION: u32 = 1;
+
+
+/// The contents of the store file.
+#[derive(Serialize, Deserialize)]
+struct StoreFile {
+    version: u32,
+    rooms: Vec<RoomSnapshot>,
+
+    /// Files saved before payouts were persisted have none.
+    #[serde(default)]
+    payouts: Vec<PayoutBatch>,
+
+    #[serde(default)]
+    retired_ledgers: Vec<RetiredLedger>,
+}
+
+
+/// Keeps the rooms in a JSON file.
+///
+/// The file is written to a temporary file next to it and flushed to disk
+/// first, then renamed over it, so a crash mid write never leaves it half
+/// written.
+pub struct FileStore {
+    path: PathBuf,
+    tmp_path: PathBuf,
+}
+
+impl FileStore {
+    pub fn new(path: &str) -> Self {
+        Self {
+            path: PathBuf::from(path),
+            tmp_path: PathBuf::from(format!("{}.tmp", path)),
+        }
+    }
+
+    /// The directory the file is kept in.
+    fn dir(&self) -> &Path {
+        match self.path.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir,
+            _ => Path::new("."),
+        }
+    }
+}
+
+impl RoomStore for FileStore {
+    fn load(&self) -> BoxFuture<'_, Result<StoreState, StoreError>> {
+        async move {
+            let data = match fs::read(&self.path).await {
+                Ok(data) => data,
+                Err(e) if e.kind() == ErrorKind::NotFound => return Ok(StoreState::default()),
+                Err(e) => return Err(StoreError::Io(e)),
+            };
+
+            let file: StoreFile = serde_json::from_slice(&data)
+                .map_err(StoreError::Format)?;
+
+            if file.version != FORMAT_VERSION {
+                let e = DeError::custom(format!("unsupported version {}", file.version));
+                return Err(StoreError::Format(e))
+            }
+
+            Ok(StoreState {
+                rooms: file.rooms,
+                payouts: file.payouts,
+                retired_ledgers: file.retired_ledgers,
+            })
+        }.boxed()
+    }
+
+    fn save(&self, state: StoreState) -> BoxFuture<'_, Result<(), StoreError>> {
+        async move {
+            let file = StoreFile {
+                version: FORMAT_VERSION,
+                rooms: state.rooms,
+                payouts: state.payouts,
+                retired_ledgers: state.retired_ledgers,
+            };
+            let data = serde_json::to_vec(&file).map_err(StoreError::Format)?;
+
+            let mut tmp = fs::File::create(&self.tmp_path).await.map_err(StoreError::Io)?;
+            tmp.write_all(&data).await.map_err(StoreError::Io)?;
+            tmp.sync_all().await.map_err(StoreError::Io)?;
+            drop(tmp);
+
+            fs::rename(&self.tmp_path, &self.path).await.map_err(StoreError::Io)?;
+
+            // The rename is only on disk once the directory is.
+            let dir = fs::File::open(self.dir()).await.map_err(StoreError::Io)?;
+            dir.sync_all().await.map_err(StoreError::Io)?;
+
+            Ok(())
+        }.boxed()
+    }
+
+    /// Checks the directory the file is written to exists and that
+    /// neither it nor the file are read only.
+    fn check(&self) -> BoxFuture<'_, Result<(), StoreError>> {
+        async move {
+            let dir = self.dir();
+
+            let meta = fs::metadata(dir).await.map_err(StoreError::Io)?;
+            if !meta.is_dir() | meta.permissions().readonly() {
+                return Err(read_only(dir))
+            }
+
+            match fs::metadata(&self.path).await {
+                Ok(meta) if meta.permissions().readonly() => Err(read_only(&self.path)),
+                Ok(_) => Ok(()),
+                Err(e) if e.kind() == ErrorKind::NotFound => Ok(()),
+                Err(e) => Err(StoreError::Io(e)),
+            }
+        }.boxed()
+    }
+}
+
+
+fn read_only(path: &Path) -> StoreError {
+    let e = io::Error::new(
+        ErrorKind::PermissionDenied,
+        format!("{} can not be written to", path.display()),
+    );
+    StoreError::Io(e)
+}
@@ -1,8 +1,8 @@
-use std::time::Duration;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 const GIGABYTE: f64 = (1024 * 1024 * 1024) as f64;
 const MEGABYTE: f64 = (1024 * 1024) as f64;
-const KILOBYTE: f64 = 1024 as f64;
+const KILOBYTE: f64 = 1024_f64;
 
 
 /// Formats N amount of bytes into their readable form.
@@ -60,3 +60,11 @@ pub fn div_mod(main: u64, divider: u64) -> (u64, u64) {
     (whole, rem)
 }
 
+
+/// The current unix timestamp in milliseconds.
+pub fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
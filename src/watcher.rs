@@ -0,0 +1,213 @@
+use tokio::time::{Duration, Instant};
+use serde::Serialize;
+use rand::Rng;
+
+use crate::config;
+use crate::utils;
+
+/// If a watcher stays up for this long before exiting its earlier
+/// failures are forgotten and the backoff starts again from the base.
+const HEALTHY_AFTER: Duration = Duration::from_secs(600);
+
+
+/// What a room's stats watcher is currently doing.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum WatcherState {
+    /// The watcher task is running.
+    Running,
+
+    /// The watcher exited and is waiting to be restarted.
+    BackingOff,
+}
+
+
+/// How a watcher task ended.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum WatcherExit {
+    /// The watcher gave up after too many errors.
+    GaveUp,
+
+    /// The watcher panicked.
+    Panicked,
+}
+
+
+/// The health of a room's stats watcher as tracked by the supervisor.
+#[derive(Debug, Clone, Serialize)]
+pub struct WatcherHealth {
+    /// What the watcher is currently doing.
+    pub state: WatcherState,
+
+    /// The amount of times the watcher has been restarted.
+    pub restarts: usize,
+
+    /// The amount of times the watcher has exited without staying up
+    /// long enough to be considered healthy.
+    pub consecutive_failures: u32,
+
+    /// How the watcher last exited.
+    pub last_exit: Option<WatcherExit>,
+
+    /// When the watcher last exited as a unix timestamp in ms.
+    pub last_exit_at: Option<u64>,
+
+    /// When the watcher will next be restarted as a unix timestamp in ms.
+    pub next_restart_at: Option<u64>,
+
+    /// When the current watcher task was started.
+    #[serde(skip)]
+    started_at: Instant,
+
+    /// When the watcher should next be restarted.
+    #[serde(skip)]
+    restart_at: Option<Instant>,
+}
+
+impl WatcherHealth {
+    /// The health of a watcher that has just been started.
+    pub fn new() -> Self {
+        Self {
+            state: WatcherState::Running,
+            restarts: 0,
+            consecutive_failures: 0,
+            last_exit: None,
+            last_exit_at: None,
+            next_restart_at: None,
+            started_at: Instant::now(),
+            restart_at: None,
+        }
+    }
+
+    /// Records the watcher exiting and schedules its restart with an
+    /// exponential backoff and jitter, returning the delay.
+    pub fn exited(&mut self, exit: WatcherExit) -> Duration {
+        if self.started_at.elapsed() >= HEALTHY_AFTER {
+            self.consecutive_failures = 0;
+        }
+
+        let delay = backoff(self.consecutive_failures);
+        self.consecutive_failures += 1;
+
+        self.state = WatcherState::BackingOff;
+        self.last_exit = Some(exit);
+        self.last_exit_at = Some(utils::now_millis());
+        self.next_restart_at = Some(utils::now_millis() + delay.as_millis() as u64);
+        self.restart_at = Some(Instant::now() + delay);
+
+        delay
+    }
+
+    /// If the watcher is backing off and its delay has passed.
+    pub fn restart_due(&self) -> bool {
+        self.restart_at
+            .map(|at| Instant::now() >= at)
+            .unwrap_or(false)
+    }
+
+    /// Records the watcher being started again.
+    pub fn restarted(&mut self) {
+        self.state = WatcherState::Running;
+        self.restarts += 1;
+        self.next_restart_at = None;
+        self.restart_at = None;
+        self.started_at = Instant::now();
+    }
+}
+
+
+/// Works out how long to wait before a restart, doubling from
+/// `WATCHER_BACKOFF_BASE` for each failure up to `WATCHER_BACKOFF_MAX`
+/// with up to half of it again added as jitter so watchers that failed
+/// together do not all restart together.
+fn backoff(failures: u32) -> Duration {
+    let base = config::get().watcher_backoff_base;
+    let max = config::get().watcher_backoff_max;
+
+    let secs = base
+        .saturating_mul(2u64.saturating_pow(failures))
+        .min(max);
+    let jitter = rand::thread_rng().gen_range(0..=secs * 500);
+
+    Duration::from_millis(secs * 1000 + jitter)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Checks a delay is `secs` plus at most half of it again as jitter.
+    fn assert_delay(delay: Duration, secs: u64) {
+        let min = Duration::from_secs(secs);
+        let max = min + min / 2;
+        assert!((min..=max).contains(&delay), "{:?} is not {}s plus jitter", delay, secs);
+    }
+
+    #[test]
+    fn backoff_doubles_for_each_failure() {
+        config::init_default();
+        let base = config::get().watcher_backoff_base;
+
+        for failures in 0..4 {
+            assert_delay(backoff(failures), base * 2u64.pow(failures));
+        }
+    }
+
+    #[test]
+    fn backoff_stops_at_the_max() {
+        config::init_default();
+        let max = config::get().watcher_backoff_max;
+
+        assert_delay(backoff(20), max);
+        assert_delay(backoff(u32::MAX), max);
+    }
+
+    #[test]
+    fn jitter_is_not_always_the_same() {
+        config::init_default();
+        let delays: Vec<Duration> = (0..20).map(|_| backoff(3)).collect();
+        assert!(delays.iter().any(|delay| *delay != delays[0]));
+    }
+
+    #[test]
+    fn exiting_schedules_a_restart() {
+        config::init_default();
+        let base = config::get().watcher_backoff_base;
+
+        let mut health = WatcherHealth::new();
+        assert_delay(health.exited(WatcherExit::GaveUp), base);
+        assert_eq!(health.state, WatcherState::BackingOff);
+        assert_eq!(health.last_exit, Some(WatcherExit::GaveUp));
+        assert!(health.next_restart_at.is_some());
+        assert!(!health.restart_due());
+
+        health.restarted();
+        assert_eq!(health.state, WatcherState::Running);
+        assert_eq!(health.restarts, 1);
+        assert!(health.next_restart_at.is_none());
+
+        assert_delay(health.exited(WatcherExit::Panicked), base * 2);
+        assert_eq!(health.consecutive_failures, 2);
+    }
+
+    #[test]
+    fn staying_up_resets_the_backoff() {
+        config::init_default();
+        let base = config::get().watcher_backoff_base;
+
+        let mut health = WatcherHealth::new();
+        for _ in 0..3 {
+            health.exited(WatcherExit::GaveUp);
+            health.restarted();
+        }
+        assert_eq!(health.consecutive_failures, 3);
+
+        health.started_at = Instant::now()
+            .checked_sub(HEALTHY_AFTER)
+            .expect("the clock has been running for longer than HEALTHY_AFTER");
+        assert_delay(health.exited(WatcherExit::GaveUp), base);
+        assert_eq!(health.consecutive_failures, 1);
+    }
+}
@@ -1,124 +1,778 @@
 use warp::ws::{WebSocket, Message};
+use tokio::sync::mpsc;
+use tokio::sync::mpsc::error::TrySendError;
+use tokio::sync::broadcast::error::RecvError;
+use tokio::time::{self, Duration, Instant};
+use std::sync::Arc;
+use std::sync::atomic::AtomicU64;
 use std::sync::atomic::Ordering::Relaxed;
 use futures::stream::SplitSink;
 use futures::{SinkExt, StreamExt};
+use tracing::{Instrument, Span};
 
-use crate::managers::{RoomReceiver, RoomManager};
-use crate::opcodes;
+use crate::managers::{Room, RoomReceiver, RoomManager};
+use crate::events::{
+    self,
+    ClientEvent,
+    ClientMessage,
+    GatewayEvent,
+    InvalidFrame,
+    MessageContent,
+    MessageSender,
+    Ready,
+    Hello,
+    Resume,
+    Resumed,
+};
+use crate::identity::{self, UserProfile};
+use crate::opcodes::{self, CloseCode};
+use crate::resume::RoomEvent;
+use crate::shutdown::Phase;
+use crate::metrics;
+use crate::config::{self, LagPolicy};
+use crate::utils;
+
+use uuid::Uuid;
+
+type DirectSender = mpsc::Sender<Message>;
+type DirectReceiver = mpsc::Receiver<Message>;
+
+/// The max amount of frames waiting to be sent on a connection's direct
+/// channel, a client that stops reading the replies to what it sends is
+/// disconnected once this many are waiting.
+const DIRECT_CHANNEL_SIZE: usize = 64;
+
+/// The max amount of characters in a reaction, some emojis are made up
+/// of several characters so this is a bit more than one.
+const MAX_REACTION_LENGTH: usize = 16;
+
+/// The max amount of characters in a custom event name.
+const MAX_EVENT_NAME_LENGTH: usize = 64;
+
+static NEXT_CONNECTION_ID: AtomicU64 = AtomicU64::new(0);
 
 
 /// Handles a room client in the form of a websocket connection.
 ///
 /// If a room does not exist the websocket is just immediately closed
-/// and ignored.
+/// and ignored, if the client asked for a protocol version we do not
+/// speak the websocket is closed with `CLOSE_UNSUPPORTED_VERSION`.
+///
+/// The client is sent a hello with the heartbeat interval and must
+/// identify or resume a dropped session before it joins the room.
+///
+/// Everything the connection logs is recorded in a `connection` span
+/// with the room id, and the connection and user ids once it has joined.
+#[tracing::instrument(
+    name = "connection",
+    parent = None,
+    skip_all,
+    fields(room_id = %room_id, connection_id, user_id),
+)]
 pub async fn connect_client(
-    ws: WebSocket,
+    mut ws: WebSocket,
     room_id: String,
+    version: u8,
     rooms: RoomManager,
 ) {
-    let receiver = {
-        if let Some(room) = rooms.get(&room_id) {
-            room.subscribe()
-        } else {
-            println!(
-               "[ ROOM {} ] Unknown room attempted join, \
-                 terminating conn.",
-              &room_id
-            );
-            let _ = ws.close().await;
+    let _guard = rooms.track_connection();
+
+    if !events::SUPPORTED_VERSIONS.contains(&version) {
+        tracing::info!(version, "Client asked for an unsupported protocol version, terminating conn");
+        upgrade_failed("unsupported_version");
+        close_with(ws, opcodes::CLOSE_UNSUPPORTED_VERSION, "Unsupported protocol version").await;
+        return;
+    }
+
+    if rooms.get(&room_id).is_none() {
+        tracing::info!("Unknown room attempted join, terminating conn");
+        upgrade_failed("unknown_room");
+        let _ = ws.close().await;
+        return;
+    }
+
+    let hello = GatewayEvent::Hello(Hello {
+        heartbeat_interval: config::get().heartbeat_interval * 1000,
+        version,
+    });
+    if ws.send(Message::text(hello.to_frame())).await.is_err() {
+        return;
+    }
+
+    let handshake = match wait_for_handshake(&mut ws).await {
+        Ok(handshake) => handshake,
+        Err((code, reason)) => {
+            if code == opcodes::CLOSE_AUTHENTICATION_FAILED {
+                upgrade_failed("authentication_failed");
+            } else {
+                upgrade_failed("not_identified");
+            }
+            close_with(ws, code, reason).await;
+            return;
+        }
+    };
+
+    let start = {
+        let room = match rooms.get(&room_id) {
+            Some(room) => room,
+            None => {
+                upgrade_failed("unknown_room");
+                let _ = ws.close().await;
+                return;
+            }
+        };
+
+        match handshake {
+            Handshake::Identify(user) => {
+                let connection_id = NEXT_CONNECTION_ID.fetch_add(1, Relaxed);
+                let session_id = Uuid::new_v4().to_string();
+                room.open_session(session_id.clone(), connection_id, user.id.clone());
+
+                Some(SessionStart {
+                    connection_id,
+                    session_id,
+                    user,
+                    room: room.value().clone(),
+                    receiver: room.subscribe(),
+                    missed: None,
+                })
+            },
+            Handshake::Resume(user, resume) => {
+                room.resume_session(&resume.session_id, &user.id, resume.seq)
+                    .map(|(connection_id, receiver, missed)| SessionStart {
+                        connection_id,
+                        session_id: resume.session_id,
+                        user,
+                        room: room.value().clone(),
+                        receiver,
+                        missed: Some(missed),
+                    })
+            },
+        }
+    };
+
+    let start = match start {
+        Some(start) => start,
+        None => {
+            tracing::info!("Client failed to resume session, terminating conn");
+            upgrade_failed("invalid_session");
+            close_with(ws, opcodes::CLOSE_INVALID_SESSION, "Session can not be resumed").await;
             return;
         }
     };
 
+    Span::current()
+        .record("connection_id", start.connection_id)
+        .record("user_id", start.user.id.as_str());
+
     handle_client(
         ws,
         &rooms,
-        room_id.clone(),
-        receiver
+        room_id,
+        start,
     ).await;
+}
+
+
+/// How a client started its session.
+enum Handshake {
+    /// The client identified as a new session.
+    Identify(UserProfile),
+
+    /// The client wants to resume a dropped session.
+    Resume(UserProfile, Resume),
+}
+
+
+/// A session that has been set up and is ready to join the room.
+struct SessionStart {
+    /// The id of the connection, a resumed session keeps its old one.
+    connection_id: u64,
+
+    /// The id the session can be resumed with.
+    session_id: String,
+
+    /// The user the connection identified as.
+    user: UserProfile,
+
+    /// The room the session is joining.
+    room: Room,
+
+    /// The subscription to the room's broadcast channel.
+    receiver: RoomReceiver,
+
+    /// The events a resumed session missed, None for a new session.
+    missed: Option<Vec<Arc<RoomEvent>>>,
+}
+
+
+/// Waits for the client to send an `OP_IDENTIFY` or `OP_RESUME` frame
+/// and validates its session token.
+///
+/// Heartbeats are acknowledged and legacy `"ping"` frames are ignored,
+/// anything else sent first or not sending either within
+/// `IDENTIFY_TIMEOUT` seconds gives the close code and reason to end the
+/// connection with.
+async fn wait_for_handshake(
+    ws: &mut WebSocket,
+) -> Result<Handshake, (CloseCode, &'static str)> {
+    let timeout = Duration::from_secs(config::get().identify_timeout);
+    let event = time::timeout(timeout, async {
+        while let Some(Ok(msg)) = ws.next().await {
+            if msg.is_ping() | msg.is_pong() {
+                continue;
+            }
+
+            let msg = msg.to_str().ok()?;
+            if msg == "ping" {
+                continue;
+            }
+
+            match serde_json::from_str::<ClientEvent>(msg) {
+                Ok(ClientEvent::Heartbeat) => {
+                    let ack = GatewayEvent::HeartbeatAck.to_frame();
+                    ws.send(Message::text(ack)).await.ok()?;
+                },
+                Ok(event @ ClientEvent::Identify(_)) => return Some(event),
+                Ok(event @ ClientEvent::Resume(_)) => return Some(event),
+                _ => return None,
+            }
+        }
+
+        None
+    }).await;
+
+    let event = match event {
+        Ok(Some(event)) => event,
+        Ok(None) => return Err((opcodes::CLOSE_NOT_AUTHENTICATED, "Not identified")),
+        Err(_) => return Err((opcodes::CLOSE_NOT_AUTHENTICATED, "Identify timed out")),
+    };
 
-    if let Some(room) = rooms.get(&room_id) {
-        room.member_leave();
+    let token = match &event {
+        ClientEvent::Identify(identify) => &identify.token,
+        ClientEvent::Resume(resume) => &resume.token,
+        _ => unreachable!(),
     };
+
+    let user = identity::validate_token(token)
+        .await
+        .map_err(|error| {
+            tracing::info!(%error, "Client failed to identify, terminating conn");
+            (opcodes::CLOSE_AUTHENTICATION_FAILED, "Authentication failed")
+        })?;
+
+    match event {
+        ClientEvent::Resume(resume) => Ok(Handshake::Resume(user, resume)),
+        _ => Ok(Handshake::Identify(user)),
+    }
 }
 
 
-/// Handles a client with a room that exists.
+/// Handles an identified client with a room that exists.
 ///
-/// This spawns a worker task that emits messages from the broadcast
-/// receiver to the websocket stream.
+/// A new session is added to the room's presence and sent a snapshot of
+/// everyone in the room, a resumed session is sent the events it missed
+/// instead. This spawns a worker task that emits messages from the
+/// broadcast receiver and the connection's own direct channel to the
+/// websocket stream.
 ///
 /// The websocket stays alive until the receiver half of the websocket
-/// returns None resulting in a client disconnect.
+/// returns None resulting in a client disconnect, or the client stops
+/// heartbeating for longer than `HEARTBEAT_TIMEOUT` seconds in which case
+/// it is closed with `CLOSE_SESSION_TIMED_OUT`. Connections still open
+/// once the gateway's shutdown drain period is over are closed with
+/// `CLOSE_SERVICE_RESTART` and connections to a room that is deleted are
+/// closed with `CLOSE_ROOM_CLOSED`. A client that lets its direct channel
+/// fill up is dropped without a close frame. Unless the client closed the
+/// connection normally its session is kept for `RESUME_GRACE` seconds.
 async fn handle_client(
     ws: WebSocket,
     rooms: &RoomManager,
     room_id: String,
-    receiver: RoomReceiver,
+    start: SessionStart,
 ) {
+    let _guard = start.room.track_connection();
+    let mut room_closed = start.room.closed();
     let (ws_tx, mut ws_rx) = ws.split();
+    let (direct_tx, direct_rx) = mpsc::channel(DIRECT_CHANNEL_SIZE);
+
+    let backlog = match &start.missed {
+        Some(missed) => {
+            let resumed = GatewayEvent::Resumed(Resumed {
+                session_id: start.session_id.clone(),
+                replayed: missed.len(),
+            });
 
-    tokio::spawn(watch_messages(ws_tx, receiver));
+            std::iter::once(Message::text(resumed.to_frame()))
+                .chain(missed.iter().map(|event| Message::text(event.frame.clone())))
+                .collect()
+        },
+        None => Vec::new(),
+    };
 
-    {
-        if let Some(room) = rooms.get(&room_id) {
-            room.member_join();
+    let mut writer = tokio::spawn(watch_messages(
+        ws_tx,
+        start.room,
+        start.receiver,
+        direct_rx,
+        backlog,
+    ).in_current_span());
 
-            if room.is_live.load(Relaxed) {
-                let url = format!("{}/live/{}.m3u8", &room.live_server, &room.room_id);
-                let payload = serde_json::json!({
-                    "opcode":  opcodes::OP_LIVE_READY,
-                    "payload": {
-                        "stream_url": url,
-                    }
-                });
-                room.send(serde_json::to_string(&payload).unwrap())
+    let mut conn = Connection {
+        id: start.connection_id,
+        session_id: start.session_id,
+        room_id,
+        user: start.user,
+        direct: direct_tx,
+        limiter: RateLimiter::new(),
+        last_heartbeat: Instant::now(),
+        overflowed: false,
+    };
+
+    if start.missed.is_none() {
+        conn.send(GatewayEvent::Ready(Ready {
+            connection_id: conn.id,
+            session_id: conn.session_id.clone(),
+            user: conn.user.clone(),
+        }));
+
+        if let Some(room) = rooms.get(&conn.room_id) {
+            room.member_join(conn.id, conn.user.clone());
+            conn.send(room.presence_snapshot_event());
+
+            if let Some(event) = room.stream_state_event() {
+                conn.send(event);
             }
         };
     }
 
+    let mut closed_normally = false;
+    let heartbeat_timeout = Duration::from_secs(config::get().heartbeat_timeout);
+    let mut heartbeat = time::interval(Duration::from_secs(config::get().heartbeat_interval));
+    let mut shutdown = rooms.shutdown_phase();
+
+    loop {
+        if conn.overflowed {
+            tracing::warn!("Client is not reading its frames, dropping conn");
+            writer.abort();
+            break;
+        }
+
+        let msg = tokio::select! {
+            msg = ws_rx.next() => match msg {
+                Some(Ok(msg)) => msg,
+                _ => break,
+            },
+            // The writer only stops if the websocket is broken or the
+            // connection was closed for lagging.
+            _ = &mut writer => break,
+            _ = heartbeat.tick() => {
+                if conn.last_heartbeat.elapsed() > heartbeat_timeout {
+                    tracing::info!("Client stopped heartbeating, terminating conn");
+                    conn.close(opcodes::CLOSE_SESSION_TIMED_OUT, "Session timed out");
+                    break;
+                }
 
-    while let Some(msg) = ws_rx.next().await {
-        let msg = match msg {
-            Ok(msg) => msg,
-            Err(_) => break,
+                conn.queue(Message::ping(Vec::new()));
+                continue;
+            },
+            _ = shutdown.wait_for(|phase| *phase == Phase::Closing) => {
+                conn.close(opcodes::CLOSE_SERVICE_RESTART, "Gateway restarting");
+                break;
+            },
+            _ = room_closed.wait_for(|closed| *closed) => {
+                conn.close(opcodes::CLOSE_ROOM_CLOSED, "Room closed");
+                break;
+            },
         };
 
+        // Any frame from the client proves the connection is alive.
+        conn.last_heartbeat = Instant::now();
+
+        if msg.is_ping() | msg.is_pong() {
+            continue;
+        }
+
+        if msg.is_close() {
+            closed_normally = matches!(msg.close_frame(), Some((1000, _)));
+            break;
+        }
+
         if !msg.is_text() {
             break;
         }
 
         if let Ok(msg) = msg.to_str() {
             if msg != "ping" {
-                break;
+                conn.handle_frame(rooms, msg);
             }
         } else {
             break;
         }
     };
 
-    println!(
-        "[ ROOM {} ] Client disconnected.",
-        &room_id
-    );
+    tracing::info!(closed_normally, "Client disconnected");
+
+    if let Some(room) = rooms.get(&conn.room_id) {
+        if closed_normally {
+            room.close_session(&conn.session_id);
+        } else {
+            room.suspend_session(conn.session_id.clone());
+        }
+    };
+}
+
+
+/// The state of a single client connection.
+struct Connection {
+    /// The gateway unique id of this connection.
+    id: u64,
+
+    /// The id this connection's session can be resumed with.
+    session_id: String,
+
+    /// The room the connection is in.
+    room_id: String,
+
+    /// The user the connection identified as.
+    user: UserProfile,
+
+    /// Sends frames to only this connection.
+    direct: DirectSender,
 
+    /// Limits how often the client can send messages.
+    limiter: RateLimiter,
 
+    /// When the client last proved it is still alive.
+    last_heartbeat: Instant,
 
+    /// If the direct channel was full when a frame was sent down it.
+    overflowed: bool,
 }
 
-/// Watches for messages from the broadcast channel and sends them to the
-/// websocket, this will end early if the websocket experiences and error.
-async fn watch_messages(mut ws: SplitSink<WebSocket, Message>, mut rx: RoomReceiver) {
-    while let Ok(msg) = rx.recv().await {
-        if let Err(_) = ws.send(Message::text(msg)).await {
+impl Connection {
+    /// Sends a frame down the direct channel, marking the connection as
+    /// overflowed if it is full.
+    fn queue(&mut self, msg: Message) {
+        if let Err(TrySendError::Full(_)) = self.direct.try_send(msg) {
+            self.overflowed = true;
+        }
+    }
+
+    /// Sends an event to only this connection.
+    fn send(&mut self, event: GatewayEvent) {
+        self.queue(Message::text(event.to_frame()));
+    }
+
+    /// Closes this connection with a gateway close code and reason.
+    fn close(&mut self, code: CloseCode, reason: &'static str) {
+        self.queue(Message::close_with(code, reason));
+    }
+
+    /// Tells the client a frame it sent was refused.
+    fn refuse(&mut self, code: &'static str, message: impl Into<String>) {
+        self.send(GatewayEvent::Invalid(InvalidFrame {
+            code,
+            message: message.into(),
+        }));
+    }
+
+    /// Validates a text frame sent by the client and acts on it.
+    ///
+    /// Anything the gateway refuses is answered with an `OP_INVALID`
+    /// event rather than closing the connection.
+    fn handle_frame(&mut self, rooms: &RoomManager, frame: &str) {
+        let event = match serde_json::from_str::<ClientEvent>(frame) {
+            Ok(event) => event,
+            Err(e) => {
+                self.refuse("invalid_frame", e.to_string());
+                return
+            }
+        };
+
+        match event {
+            ClientEvent::Heartbeat => {
+                self.send(GatewayEvent::HeartbeatAck);
+            },
+            ClientEvent::SendMessage(content) => {
+                if !self.limiter.try_acquire() {
+                    self.refuse("rate_limited", "You are sending messages too fast.");
+                    return
+                }
+
+                if let Err(reason) = validate_message(&content) {
+                    self.refuse("invalid_message", reason);
+                    return
+                }
+
+                if let Some(room) = rooms.get(&self.room_id) {
+                    room.emit(GatewayEvent::ClientMessage(ClientMessage {
+                        sender: MessageSender {
+                            connection_id: self.id,
+                            user: self.user.clone(),
+                        },
+                        timestamp: utils::now_millis(),
+                        content,
+                    }));
+                }
+            },
+            ClientEvent::Identify(_) | ClientEvent::Resume(_) => {
+                self.refuse("already_identified", "This connection has already identified.");
+            },
+        }
+    }
+}
+
+
+/// Checks a client message is within the gateway's limits.
+fn validate_message(content: &MessageContent) -> Result<(), String> {
+    match content {
+        MessageContent::Chat { content } => {
+            let length = content.chars().count();
+
+            if content.trim().is_empty() {
+                Err("Chat messages can not be empty.".to_string())
+            } else if length > config::get().max_chat_length {
+                Err(format!(
+                    "Chat messages can be at most {} characters.",
+                    config::get().max_chat_length,
+                ))
+            } else {
+                Ok(())
+            }
+        },
+        MessageContent::Reaction { emoji } => {
+            let length = emoji.chars().count();
+
+            if (length == 0) | (length > MAX_REACTION_LENGTH) {
+                Err("Reactions must be a single emoji.".to_string())
+            } else {
+                Ok(())
+            }
+        },
+        MessageContent::Custom { name, .. } => {
+            let length = name.chars().count();
+
+            if (length == 0) | (length > MAX_EVENT_NAME_LENGTH) {
+                Err(format!(
+                    "Custom event names must be 1 to {} characters.",
+                    MAX_EVENT_NAME_LENGTH,
+                ))
+            } else {
+                Ok(())
+            }
+        },
+    }
+}
+
+
+/// A token bucket limiting how many messages a connection can send.
+struct RateLimiter {
+    /// The amount of messages that can currently be sent.
+    tokens: f64,
+
+    /// When the bucket was last refilled.
+    last_refill: Instant,
+}
+
+impl RateLimiter {
+    /// Creates a full bucket.
+    fn new() -> Self {
+        Self {
+            tokens: config::get().message_burst,
+            last_refill: Instant::now(),
+        }
+    }
+
+    /// Refills the bucket and takes a token if there is one.
+    fn try_acquire(&mut self) -> bool {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.last_refill).as_secs_f64();
+        self.last_refill = now;
+
+        self.tokens = (self.tokens + elapsed * config::get().message_rate)
+            .min(config::get().message_burst);
+
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+
+/// Watches for messages from the broadcast channel and the connection's
+/// direct channel and sends them to the websocket, this will end early if
+/// the websocket experiences and error, the connection is dropped or a
+/// close frame is sent down the direct channel.
+///
+/// The backlog is sent before anything else. If the client falls too far
+/// behind the broadcast channel the lag is recorded on the room and it is
+/// either resynced or closed with `CLOSE_LAGGED` depending on `LAG_POLICY`.
+async fn watch_messages(
+    mut ws: SplitSink<WebSocket, Message>,
+    room: Room,
+    mut rx: RoomReceiver,
+    mut direct: DirectReceiver,
+    backlog: Vec<Message>,
+) {
+    for msg in backlog {
+        if ws.send(msg).await.is_err() {
+            return;
+        }
+    }
+
+    loop {
+        let msg = tokio::select! {
+            msg = rx.recv() => match msg {
+                Ok(event) => Message::text(event.frame.clone()),
+                Err(RecvError::Lagged(skipped)) => {
+                    room.record_lag(skipped);
+                    tracing::warn!(skipped, "Client lagged behind");
+
+                    match config::get().lag_policy {
+                        LagPolicy::Resync => {
+                            let (fresh, resync) = room.resync(skipped);
+                            rx = fresh;
+                            Message::text(resync.to_frame())
+                        },
+                        LagPolicy::Disconnect => {
+                            Message::close_with(opcodes::CLOSE_LAGGED, "Connection lagged behind")
+                        },
+                    }
+                },
+                Err(RecvError::Closed) => break,
+            },
+            msg = direct.recv() => match msg {
+                Some(msg) => msg,
+                None => break,
+            },
+        };
+
+        let is_close = msg.is_close();
+        if ws.send(msg).await.is_err() {
+            break;
+        }
+
+        if is_close {
             break;
         }
 
-        if let Err(_) = ws.flush().await {
+        if ws.flush().await.is_err() {
             break;
         }
     }
 
-    let _ = ws.close();
+    let _ = ws.close().await;
 }
 
+
+/// Counts a websocket that was refused before it joined a room.
+pub fn upgrade_failed(reason: &str) {
+    metrics::UPGRADE_FAILURES.with_label_values(&[reason]).inc();
+}
+
+
+/// Closes the websocket with a gateway close code and reason.
+async fn close_with(mut ws: WebSocket, code: CloseCode, reason: &'static str) {
+    let _ = ws.send(Message::close_with(code, reason)).await;
+    let _ = ws.close().await;
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::Value;
+
+    /// Pretends the bucket was last refilled `secs` earlier.
+    fn rewind(limiter: &mut RateLimiter, secs: u64) {
+        limiter.last_refill = Instant::now()
+            .checked_sub(Duration::from_secs(secs))
+            .expect("the clock has been running for longer than the rewind");
+    }
+
+    /// The amount of messages the limiter lets through right now.
+    fn drain(limiter: &mut RateLimiter) -> usize {
+        let mut sent = 0;
+        while limiter.try_acquire() {
+            sent += 1;
+        }
+        sent
+    }
+
+    fn chat(content: &str) -> MessageContent {
+        MessageContent::Chat { content: content.to_string() }
+    }
+
+    #[test]
+    fn burst_can_be_sent_at_once() {
+        config::init_default();
+        let burst = config::get().message_burst as usize;
+
+        let mut limiter = RateLimiter::new();
+        assert_eq!(drain(&mut limiter), burst);
+        assert!(!limiter.try_acquire());
+    }
+
+    #[test]
+    fn bucket_refills_at_the_message_rate() {
+        config::init_default();
+        let rate = config::get().message_rate as usize;
+
+        let mut limiter = RateLimiter::new();
+        drain(&mut limiter);
+
+        rewind(&mut limiter, 1);
+        assert_eq!(drain(&mut limiter), rate);
+    }
+
+    #[test]
+    fn bucket_never_holds_more_than_the_burst() {
+        config::init_default();
+        let burst = config::get().message_burst as usize;
+
+        let mut limiter = RateLimiter::new();
+        drain(&mut limiter);
+
+        rewind(&mut limiter, 3600);
+        assert_eq!(drain(&mut limiter), burst);
+    }
+
+    #[test]
+    fn chat_must_have_content() {
+        config::init_default();
+        assert!(validate_message(&chat("hi")).is_ok());
+        assert!(validate_message(&chat("")).is_err());
+        assert!(validate_message(&chat(" \n\t ")).is_err());
+    }
+
+    #[test]
+    fn chat_is_limited_in_characters() {
+        config::init_default();
+        let max = config::get().max_chat_length;
+
+        assert!(validate_message(&chat(&"a".repeat(max))).is_ok());
+        assert!(validate_message(&chat(&"a".repeat(max + 1))).is_err());
+
+        // Characters are counted rather than bytes.
+        assert!(validate_message(&chat(&"é".repeat(max))).is_ok());
+    }
+
+    #[test]
+    fn reactions_and_custom_events_are_limited() {
+        config::init_default();
+        let reaction = |emoji: &str| MessageContent::Reaction { emoji: emoji.to_string() };
+        let custom = |name: &str| MessageContent::Custom { name: name.to_string(), data: Value::Null };
+
+        assert!(validate_message(&reaction("🎉")).is_ok());
+        assert!(validate_message(&reaction("")).is_err());
+        assert!(validate_message(&reaction(&"🎉".repeat(MAX_REACTION_LENGTH + 1))).is_err());
+
+        assert!(validate_message(&custom("poll")).is_ok());
+        assert!(validate_message(&custom("")).is_err());
+        assert!(validate_message(&custom(&"a".repeat(MAX_EVENT_NAME_LENGTH + 1))).is_err());
+    }
+}